@@ -1,11 +1,9 @@
 //! A loreyawen-specific wrapper around AES-CTR
 
-use crate::crypto::cipher::generic_array::GenericArray;
-use crate::crypto::cipher::{KeyIvInit, StreamCipher};
-use crate::crypto::Aes128;
+use crate::crypto::cipher::StreamCipher;
+use crate::crypto::suite::CipherSuite;
 use crate::Direction;
 use core::marker::PhantomData;
-use ctr::Ctr128BE;
 
 /// The key used for AES keystream generation
 pub type Key = [u8; 16];
@@ -13,10 +11,12 @@ pub type Key = [u8; 16];
 pub type Address = u32;
 /// The frame counter of the message to compute the MIC for
 pub type Counter = u32;
+/// The key generation ("ratchet epoch") the message was (or is to be) sealed under
+pub type Generation = u8;
 
 /// A loreyawen-specific wrapper around AES-CTR to compute and apply a cipherstream
 #[derive(Debug, Clone, Copy)]
-pub struct AesCtrBuilder<Aes = (), Key = (), Direction = (), Address = (), Counter = ()> {
+pub struct AesCtrBuilder<Aes = (), Key = (), Direction = (), Address = (), Counter = (), Generation = ()> {
     /// The underlying implementation
     aes: Aes,
     /// The key used for AES keystream generation
@@ -27,25 +27,34 @@ pub struct AesCtrBuilder<Aes = (), Key = (), Direction = (), Address = (), Count
     address: Address,
     /// The frame counter of the message to compute the MIC for
     frame_counter: Counter,
+    /// The key generation ("ratchet epoch") the message was (or is to be) sealed under
+    generation: Generation,
 }
 impl AesCtrBuilder {
     /// Create a new cipherstream with the given key and AES implementation
     pub const fn new<Aes>(appskey: &Key) -> AesCtrBuilder<PhantomData<Aes>, Key> {
-        AesCtrBuilder { aes: PhantomData, appskey: *appskey, direction: (), address: (), frame_counter: () }
+        AesCtrBuilder {
+            aes: PhantomData,
+            appskey: *appskey,
+            direction: (),
+            address: (),
+            frame_counter: (),
+            generation: (),
+        }
     }
 }
 impl<Aes> AesCtrBuilder<PhantomData<Aes>, Key> {
     /// Set the frame direction (Uplink or Downlink)
     pub fn set_direction(self, direction: Direction) -> AesCtrBuilder<PhantomData<Aes>, Key, Direction> {
-        let Self { aes, appskey, address, frame_counter, .. } = self;
-        AesCtrBuilder { aes, appskey, direction, address, frame_counter }
+        let Self { aes, appskey, address, frame_counter, generation, .. } = self;
+        AesCtrBuilder { aes, appskey, direction, address, frame_counter, generation }
     }
 }
 impl<Aes> AesCtrBuilder<PhantomData<Aes>, Key, Direction> {
     /// Sets the address of the associated end-device
     pub fn set_address(self, address: Address) -> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address> {
-        let Self { aes, appskey, direction, frame_counter, .. } = self;
-        AesCtrBuilder { aes, appskey, direction, address, frame_counter }
+        let Self { aes, appskey, direction, frame_counter, generation, .. } = self;
+        AesCtrBuilder { aes, appskey, direction, address, frame_counter, generation }
     }
 }
 impl<Aes> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address> {
@@ -54,55 +63,41 @@ impl<Aes> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address> {
         self,
         frame_counter: Counter,
     ) -> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address, Counter> {
-        let Self { aes, appskey, direction, address, .. } = self;
-        AesCtrBuilder { aes, appskey, direction, address, frame_counter }
+        let Self { aes, appskey, direction, address, generation, .. } = self;
+        AesCtrBuilder { aes, appskey, direction, address, frame_counter, generation }
     }
 }
 impl<Aes> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address, Counter> {
+    /// Sets the key generation ("ratchet epoch") the message was (or is to be) sealed under
+    ///
+    /// # Implementation Note
+    /// Mixing the generation into block0 ties the keystream to a specific ratchet epoch, so a frame from one
+    /// generation can never be decrypted under another, even in the (practically impossible) case that the ratchet
+    /// happened to derive colliding working keys for adjacent generations.
+    pub fn set_generation(
+        self,
+        generation: Generation,
+    ) -> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address, Counter, Generation> {
+        let Self { aes, appskey, direction, address, frame_counter, .. } = self;
+        AesCtrBuilder { aes, appskey, direction, address, frame_counter, generation }
+    }
+}
+impl<Aes> AesCtrBuilder<PhantomData<Aes>, Key, Direction, Address, Counter, Generation> {
     /// Processes the given data by applying the keystream
     ///
     /// # Panics
     /// This function panics if data is longer than `255 * 16`.
     pub fn apply(self, data: &mut [u8])
     where
-        Aes: Aes128,
+        Aes: CipherSuite,
     {
         // Ensure we do not encrypt more than 256 blocks, since we must only use the last byte as counter; the other
         //  bytes are defined by LoRaWAN to pin the message context
         assert!(data.len() <= 255 * 16, "Data is too long");
 
-        // Build counter block 0 and prepare key
-        let block0 = self.block0(self.direction, self.address, self.frame_counter);
-        let iv = GenericArray::from_slice(&block0);
-        let key = GenericArray::from_slice(&self.appskey);
-
-        // Initialize the cipher and process data
-        let mut ctr: Ctr128BE<Aes> = Ctr128BE::new(key, iv);
-        ctr.apply_keystream(data);
-    }
-
-    /// Generates the implicit block0, which is used to tie the message to its context
-    #[inline]
-    fn block0(&self, direction: Direction, address: u32, frame_counter: u32) -> Key {
-        // Destructure address and counter into bytes
-        let address = address.to_le_bytes();
-        let counter = frame_counter.to_le_bytes();
-
-        // Build block0
-        #[rustfmt::skip]
-        return [
-            // Static preamble
-            0x01, 0x00, 0x00, 0x00, 0x00,
-            // The message direction
-            direction as u8,
-            // The end-device address
-            address[0], address[1], address[2], address[3],
-            // The frame counter
-            counter[0], counter[1], counter[2], counter[3],
-            // Another static byte
-            0x00,
-            // The first block has an index of 1
-            0x01
-        ];
+        // Build the keystream under the suite's own block0/nonce layout and apply it
+        let mut keystream =
+            Aes::new_keystream(&self.appskey, self.direction, self.address, self.frame_counter, self.generation);
+        keystream.apply_keystream(data);
     }
 }
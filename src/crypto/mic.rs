@@ -1,52 +1,81 @@
 //! A loreyawen-specific wrapper around AES-CMAC
 
 use crate::{
-    crypto::{cipher::generic_array::GenericArray, Aes128},
+    crypto::{
+        provider::{CryptoProvider, SoftwareProvider},
+        Aes128,
+    },
     Direction,
 };
-use cmac::{Cmac, Mac};
 use core::{fmt::Debug, marker::PhantomData};
 
 /// A loreyawen-specific wrapper around AES-CMAC to compute/validate a MIC for a message
+///
+/// # Implementation Note
+/// The `Provider` type parameter selects the [`CryptoProvider`] that actually performs the CMAC computation; it
+/// defaults to [`SoftwareProvider`], which runs AES-CMAC over `nwkskey` in RAM. A caller that wants key material to
+/// stay in e.g. a secure element can plug in a different provider instead.
+///
+/// # Current Limitation
+/// `nwkskey` is always stored here as a raw `[u8; 16]` (it comes from
+/// [`SessionState::nwkskey`](crate::SessionState::nwkskey), which only ever returns raw key bytes), and
+/// [`MicBuilderWithGeneration::compute`]/[`MicBuilderWithGeneration::verify`] require `Provider::KeyHandle = [u8; 16]`
+/// to match; a `Provider` backed by a secure element still has the raw key handed to it from here, so this is only a
+/// partial step towards keeping `nwkskey` out of this crate's RAM; see [`CryptoProvider`]'s doc comment.
 #[derive(Debug)]
-pub struct MicBuilder<Aes> {
+pub struct MicBuilder<Aes, Provider = SoftwareProvider<Aes>> {
     /// The key used for CMAC computation
     nwkskey: [u8; 16],
     /// The underlying implementation
     _aes: PhantomData<Aes>,
+    /// The underlying implementation
+    _provider: PhantomData<Provider>,
 }
-impl<Aes> MicBuilder<Aes> {
+impl<Aes, Provider> MicBuilder<Aes, Provider> {
     /// Create a new MIC builder with the given key
     pub const fn new(nwkskey: &[u8; 16]) -> Self {
-        Self { nwkskey: *nwkskey, _aes: PhantomData }
+        Self { nwkskey: *nwkskey, _aes: PhantomData, _provider: PhantomData }
     }
 
     /// Sets the direction of the message to compute/validate the MIC for
-    pub fn set_direction(&self, direction: Direction) -> MicBuilderWithDirection<Aes> {
-        MicBuilderWithDirection { nwkskey: self.nwkskey, direction, _aes: self._aes }
+    pub fn set_direction(&self, direction: Direction) -> MicBuilderWithDirection<Aes, Provider> {
+        MicBuilderWithDirection {
+            nwkskey: self.nwkskey,
+            direction,
+            _aes: self._aes,
+            _provider: self._provider,
+        }
     }
 }
 
 /// A loreyawen-specific wrapper around AES-CMAC to compute or validate a MIC for a message
 #[derive(Debug)]
-pub struct MicBuilderWithDirection<Aes> {
+pub struct MicBuilderWithDirection<Aes, Provider = SoftwareProvider<Aes>> {
     /// The key used for CMAC computation
     nwkskey: [u8; 16],
     /// The direction of the message to compute/validate the MIC for
     direction: Direction,
     /// The underlying implementation
     _aes: PhantomData<Aes>,
+    /// The underlying implementation
+    _provider: PhantomData<Provider>,
 }
-impl<Aes> MicBuilderWithDirection<Aes> {
+impl<Aes, Provider> MicBuilderWithDirection<Aes, Provider> {
     /// Sets the address of the associated end-device
-    pub fn set_address(&self, address: u32) -> MicBuilderWithAddress<Aes> {
-        MicBuilderWithAddress { nwkskey: self.nwkskey, direction: self.direction, address, _aes: self._aes }
+    pub fn set_address(&self, address: u32) -> MicBuilderWithAddress<Aes, Provider> {
+        MicBuilderWithAddress {
+            nwkskey: self.nwkskey,
+            direction: self.direction,
+            address,
+            _aes: self._aes,
+            _provider: self._provider,
+        }
     }
 }
 
 /// A loreyawen-specific wrapper around AES-CMAC to compute or validate a MIC for a message
 #[derive(Debug)]
-pub struct MicBuilderWithAddress<Aes> {
+pub struct MicBuilderWithAddress<Aes, Provider = SoftwareProvider<Aes>> {
     /// The key used for CMAC computation
     nwkskey: [u8; 16],
     /// The direction of the message to compute/validate the MIC for
@@ -55,23 +84,26 @@ pub struct MicBuilderWithAddress<Aes> {
     address: u32,
     /// The underlying implementation
     _aes: PhantomData<Aes>,
+    /// The underlying implementation
+    _provider: PhantomData<Provider>,
 }
-impl<Aes> MicBuilderWithAddress<Aes> {
+impl<Aes, Provider> MicBuilderWithAddress<Aes, Provider> {
     /// Set the frame counter of the message to compute/validate the MIC for
-    pub fn set_frame_counter(&self, frame_counter: u32) -> MicBuilderWithFrameCounter<Aes> {
+    pub fn set_frame_counter(&self, frame_counter: u32) -> MicBuilderWithFrameCounter<Aes, Provider> {
         MicBuilderWithFrameCounter {
             nwkskey: self.nwkskey,
             direction: self.direction,
             address: self.address,
             frame_counter,
             _aes: self._aes,
+            _provider: self._provider,
         }
     }
 }
 
 /// A loreyawen-specific wrapper around AES-CMAC to compute or validate a MIC for a message
 #[derive(Debug)]
-pub struct MicBuilderWithFrameCounter<Aes> {
+pub struct MicBuilderWithFrameCounter<Aes, Provider = SoftwareProvider<Aes>> {
     /// The key used for CMAC computation
     nwkskey: [u8; 16],
     /// The direction of the message to compute the MIC for
@@ -82,56 +114,94 @@ pub struct MicBuilderWithFrameCounter<Aes> {
     frame_counter: u32,
     /// The underlying implementation
     _aes: PhantomData<Aes>,
+    /// The underlying implementation
+    _provider: PhantomData<Provider>,
 }
-impl<Aes> MicBuilderWithFrameCounter<Aes>
+impl<Aes, Provider> MicBuilderWithFrameCounter<Aes, Provider> {
+    /// Sets the key generation ("ratchet epoch") the message was (or is to be) sealed under
+    ///
+    /// # Implementation Note
+    /// Mixing the generation into `block0` ties the MIC to a specific ratchet epoch, so a frame sealed under
+    /// generation `N` can never validate against generation `N - 1` or `N + 1`, even in the (practically impossible)
+    /// case that the ratchet happened to derive colliding working keys for adjacent generations.
+    pub fn set_generation(&self, generation: u8) -> MicBuilderWithGeneration<Aes, Provider> {
+        MicBuilderWithGeneration {
+            nwkskey: self.nwkskey,
+            direction: self.direction,
+            address: self.address,
+            frame_counter: self.frame_counter,
+            generation,
+            _aes: self._aes,
+            _provider: self._provider,
+        }
+    }
+}
+
+/// A loreyawen-specific wrapper around AES-CMAC to compute or validate a MIC for a message
+#[derive(Debug)]
+pub struct MicBuilderWithGeneration<Aes, Provider = SoftwareProvider<Aes>> {
+    /// The key used for CMAC computation
+    nwkskey: [u8; 16],
+    /// The direction of the message to compute the MIC for
+    direction: Direction,
+    /// The address of the associated end-device
+    address: u32,
+    /// The frame counter of the message to compute the MIC for
+    frame_counter: u32,
+    /// The key generation ("ratchet epoch") the message was (or is to be) sealed under
+    generation: u8,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+    /// The underlying implementation
+    _provider: PhantomData<Provider>,
+}
+impl<Aes, Provider> MicBuilderWithGeneration<Aes, Provider>
 where
     Aes: Aes128,
+    Provider: CryptoProvider<KeyHandle = [u8; 16]> + Default,
 {
     /// Compute the MIC for a given message
     ///
     /// # Panics
     /// This function panics if the total message length is longer than `255` bytes.
-    pub fn compute(self, header: &[u8], payload: &[u8]) -> [u8; 8] {
+    pub fn compute(self, header: &[u8], fopts: &[u8], payload: &[u8]) -> [u8; 8] {
         // Compute and return MIC
-        let mac = self.cmac(header, payload).finalize().into_bytes();
+        let mac = self.cmac(header, fopts, payload);
         *mac.first_chunk().expect("MAC is too short")
     }
 
     /// Validates the MIC for a given message
     #[must_use]
-    pub fn verify(self, header: &[u8], payload: &[u8], expected_mic: &[u8; 8]) -> bool {
+    pub fn verify(self, header: &[u8], fopts: &[u8], payload: &[u8], expected_mic: &[u8; 8]) -> bool {
         // Ensure the message length is within our constraints
-        let total_length = header.len().saturating_add(payload.len());
+        let total_length = header.len().saturating_add(fopts.len()).saturating_add(payload.len());
         let ..=255 = total_length else {
             // Reject the message as it is too long
             return false;
         };
 
-        // Compute and validate MIC
-        self.cmac(header, payload).verify_truncated_left(expected_mic).is_ok()
+        // Compute and validate MIC in constant time
+        let mac = self.cmac(header, fopts, payload);
+        #[allow(clippy::indexing_slicing)]
+        let computed = &mac[..expected_mic.len()];
+        let diff = computed.iter().zip(expected_mic).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+        diff == 0
     }
 
-    /// Initializes a CMAC state with the given message but does not finalize it
-    fn cmac(&self, header: &[u8], payload: &[u8]) -> Cmac<Aes> {
+    /// Feeds block0, header, fopts and payload to the provider, which computes the (untruncated) CMAC
+    fn cmac(&self, header: &[u8], fopts: &[u8], payload: &[u8]) -> [u8; 16] {
         // Compute total length
-        let message_len = header.len().saturating_add(payload.len());
+        let message_len = header.len().saturating_add(fopts.len()).saturating_add(payload.len());
         let message_len = u8::try_from(message_len).expect("Message is too large");
 
-        // Build block 0 and prepare key
-        let block0 = self.block0(self.direction, self.address, self.frame_counter, message_len);
-        let key = GenericArray::from_slice(&self.nwkskey);
-
-        // Compute CMAC
-        let mut cmac: Cmac<Aes> = Cmac::new(key);
-        cmac.update(&block0);
-        cmac.update(header);
-        cmac.update(payload);
-        cmac
+        // Build block0 and hand everything off to the provider
+        let block0 = self.block0(self.direction, self.address, self.frame_counter, self.generation, message_len);
+        Provider::default().cmac(&self.nwkskey, &block0, header, fopts, payload)
     }
 
     /// Generates the implicit block0, which is used to tie the message to its context
     #[inline]
-    fn block0(&self, direction: Direction, address: u32, frame_counter: u32, message_len: u8) -> [u8; 16] {
+    fn block0(&self, direction: Direction, address: u32, frame_counter: u32, generation: u8, message_len: u8) -> [u8; 16] {
         // Destructure address and counter into bytes
         let address = address.to_le_bytes();
         let counter = frame_counter.to_le_bytes();
@@ -147,8 +217,8 @@ where
             address[0], address[1], address[2], address[3],
             // The frame counter
             counter[0], counter[1], counter[2], counter[3],
-            // Another static byte
-            0x00,
+            // The key generation, so a frame from one ratchet epoch can never validate against another
+            generation,
             // The message length
             message_len
         ];
@@ -1,13 +1,25 @@
 //! Cryptographic primitives for Loreyawen frame encryption
 
+pub mod aescmac;
+pub mod aesctr;
+#[cfg(feature = "header-protection")]
+pub mod header_protection;
 pub mod mic;
+pub mod provider;
+pub mod ratchet;
 pub mod stream;
+pub mod suite;
 
 // Re-export the basic `cipher`-crate as its traits are used in public APIs
 pub use cipher;
 // Re-export the `aes`-crate if the feature is enabled
 #[cfg(feature = "aes")]
 pub use aes;
+// Re-export the `chacha20`/`poly1305`-crates if the feature is enabled
+#[cfg(feature = "chacha20")]
+pub use chacha20;
+#[cfg(feature = "chacha20")]
+pub use poly1305;
 
 use cipher::{generic_array::typenum::U16, BlockCipher, BlockEncrypt, KeyInit};
 
@@ -0,0 +1,63 @@
+//! A pluggable cryptographic backend, so that key material does not necessarily have to live in this crate's address
+//! space
+
+use crate::crypto::{cipher::generic_array::GenericArray, Aes128};
+use cmac::{Cmac, Mac};
+use core::marker::PhantomData;
+
+/// A cryptographic backend that can compute an AES-CMAC under an opaque key handle
+///
+/// # Implementation Note
+/// This indirection exists so that `nwkskey`/`appskey` do not necessarily have to be held in RAM by this crate: a
+/// provider backed by e.g. an ATECC608-style secure element can perform AES-CMAC internally under a handle that
+/// identifies a key which never leaves the element. [`SoftwareProvider`] is the default, in-RAM implementation used
+/// throughout this crate unless a different provider is selected.
+///
+/// # Current Limitation
+/// This is only a partial step towards that goal today: [`MicBuilder`](crate::crypto::mic::MicBuilder), the sole
+/// caller, requires `KeyHandle = [u8; 16]`, and [`SessionState::nwkskey`](crate::SessionState::nwkskey)/
+/// [`SessionState::appskey`](crate::SessionState::appskey) hand back raw `&[u8; 16]` key material unconditionally. A
+/// secure-element-backed provider therefore cannot yet keep `nwkskey`/`appskey` out of this crate's RAM end-to-end;
+/// doing so would additionally require generifying [`SessionState`](crate::SessionState) over the provider's
+/// `KeyHandle` type.
+pub trait CryptoProvider {
+    /// An opaque handle identifying a key known to this provider
+    type KeyHandle;
+
+    /// Computes the (untruncated) AES-CMAC over `block0 || header || fopts || payload` under the given key handle
+    fn cmac(&self, key: &Self::KeyHandle, block0: &[u8; 16], header: &[u8], fopts: &[u8], payload: &[u8]) -> [u8; 16];
+}
+
+/// The default [`CryptoProvider`], backed by an in-memory [`Aes128`] implementation and RustCrypto's `Cmac`
+#[derive(Debug, Clone, Copy)]
+pub struct SoftwareProvider<Aes> {
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Aes> Default for SoftwareProvider<Aes> {
+    /// Hand-written rather than `#[derive(Default)]`: the derive would add an `Aes: Default` bound even though
+    /// `Aes` only ever appears behind [`PhantomData`], making this unusable for any `Aes` that does not itself
+    /// implement `Default` (e.g. a generic `Aes128` type parameter)
+    fn default() -> Self {
+        Self { _aes: PhantomData }
+    }
+}
+impl<Aes> CryptoProvider for SoftwareProvider<Aes>
+where
+    Aes: Aes128,
+{
+    type KeyHandle = [u8; 16];
+
+    fn cmac(&self, key: &[u8; 16], block0: &[u8; 16], header: &[u8], fopts: &[u8], payload: &[u8]) -> [u8; 16] {
+        // Compute CMAC over block0, header, fopts and payload
+        let key = GenericArray::from_slice(key);
+        let mut cmac: Cmac<Aes> = Cmac::new(key);
+        cmac.update(block0);
+        cmac.update(header);
+        cmac.update(fopts);
+        cmac.update(payload);
+
+        let mac = cmac.finalize().into_bytes();
+        *mac.first_chunk().expect("MAC is too short")
+    }
+}
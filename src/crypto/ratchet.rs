@@ -0,0 +1,29 @@
+//! A CMAC-based key ratchet used to derive the next key generation from the current session keys
+
+use crate::crypto::{cipher::generic_array::GenericArray, Aes128};
+use cmac::{Cmac, Mac};
+
+/// The label used to derive the next network session key
+pub const NWKSKEY_LABEL: u8 = 0x01;
+/// The label used to derive the next application session key
+pub const APPSKEY_LABEL: u8 = 0x02;
+
+/// Derives the key for the given generation from the current key, using AES-CMAC as a KDF
+///
+/// # Implementation Note
+/// The derived key is `CMAC(old_key, label || generation)`; distinct labels are used for the network and application
+/// session keys so that ratcheting never accidentally derives the same key for both.
+pub fn ratchet<Aes>(old_key: &[u8; 16], label: u8, generation: u32) -> [u8; 16]
+where
+    Aes: Aes128,
+{
+    // Build the KDF input and compute the CMAC
+    let key = GenericArray::from_slice(old_key);
+    let mut cmac: Cmac<Aes> = Cmac::new(key);
+    cmac.update(&[label]);
+    cmac.update(&generation.to_le_bytes());
+
+    // Return the derived key
+    let mac = cmac.finalize().into_bytes();
+    *mac.first_chunk().expect("MAC is too short")
+}
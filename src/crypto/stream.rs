@@ -86,21 +86,110 @@ pub struct CipherstreamBuilderWithFrameCounter<Aes> {
     /// The underlying implementation
     _aes: PhantomData<Aes>,
 }
-impl<Aes> CipherstreamBuilderWithFrameCounter<Aes>
+impl<Aes> CipherstreamBuilderWithFrameCounter<Aes> {
+    /// Sets the key generation ("ratchet epoch") the message was (or is to be) sealed under
+    ///
+    /// # Implementation Note
+    /// Mixing the generation into `block0` ties the keystream to a specific ratchet epoch, so a frame from one
+    /// generation can never be decrypted under another, even in the (practically impossible) case that the ratchet
+    /// happened to derive colliding working keys for adjacent generations.
+    pub fn set_generation(&self, generation: u8) -> CipherstreamBuilderWithGeneration<Aes> {
+        CipherstreamBuilderWithGeneration {
+            appskey: self.appskey,
+            direction: self.direction,
+            address: self.address,
+            frame_counter: self.frame_counter,
+            generation,
+            _aes: self._aes,
+        }
+    }
+}
+
+/// A loreyawen-specific wrapper around AES-CTR to compute and apply a cipherstream
+#[derive(Debug)]
+pub struct CipherstreamBuilderWithGeneration<Aes> {
+    /// The key used for cipherstream computation
+    appskey: [u8; 16],
+    /// The direction of the message to compute the cipherstream for
+    direction: Direction,
+    /// The address of the associated end-device
+    address: u32,
+    /// The frame counter of the message to compute the cipherstream for
+    frame_counter: u32,
+    /// The key generation ("ratchet epoch") the message was (or is to be) sealed under
+    generation: u8,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Aes> CipherstreamBuilderWithGeneration<Aes>
 where
     Aes: Aes128,
 {
-    /// Processes the given data by applying the keystream
+    /// The static block0 preamble used for the payload keystream
+    const PAYLOAD_PREAMBLE: u8 = 0x01;
+    /// The static block0 preamble used for the `FOpts` keystream
+    ///
+    /// # Implementation Note
+    /// This differs from [`Self::PAYLOAD_PREAMBLE`] so that `FOpts` and the payload are domain-separated: even if
+    /// both happened to be sealed under the same key, direction, address, frame counter and generation, they never
+    /// share a keystream block, matching how standard LoRaWAN enciphers `FOpts` separately from `FRMPayload`.
+    const FOPTS_PREAMBLE: u8 = 0x02;
+
+    /// Processes the given data by applying the payload keystream
     ///
     /// # Panics
     /// This function panics if data is longer than `255 * 16`.
     pub fn apply(self, data: &mut [u8]) {
+        self.apply_with_preamble(Self::PAYLOAD_PREAMBLE, data);
+    }
+
+    /// Processes the given data by applying the `FOpts` keystream
+    ///
+    /// # Implementation Note
+    /// This uses a different block0 preamble than [`Self::apply`], so the `FOpts` keystream never collides with the
+    /// payload keystream; see [`Self::FOPTS_PREAMBLE`].
+    ///
+    /// # Panics
+    /// This function panics if data is longer than `255 * 16`.
+    pub fn apply_fopts(self, data: &mut [u8]) {
+        self.apply_with_preamble(Self::FOPTS_PREAMBLE, data);
+    }
+
+    /// Processes the given data by applying a nonce-misuse-resistant, SIV-style payload keystream derived from
+    /// `mic` instead of the frame counter
+    ///
+    /// # Implementation Note
+    /// `mic` is the on-wire MIC computed over the header, `FOpts` and the *plaintext* payload (see
+    /// [`crate::crypto::mic::MicBuilder`]); since it is transmitted alongside the ciphertext, both ends can derive the
+    /// same keystream from it directly, without first having to agree on the frame counter. Two frames that happen to
+    /// share a frame counter (e.g. after a device reset) therefore still get distinct keystreams as long as their
+    /// plaintexts differ, unlike [`Self::apply`], whose keystream is fully determined by the counter alone.
+    ///
+    /// # Panics
+    /// This function panics if data is longer than `255 * 16`.
+    pub fn apply_siv(self, mic: &[u8; 8], data: &mut [u8]) {
+        // Ensure we do not encrypt more than 256 blocks, since we must only use the last byte as counter; the other
+        //  bytes are defined by LoRaWAN to pin the message context
+        assert!(data.len() <= 255 * 16, "Data is too long");
+
+        // Build counter block 0 from the MIC instead of the frame counter, and prepare key
+        let block0 = self.block0_siv(mic, self.direction, self.address, self.generation);
+        let iv = GenericArray::from_slice(&block0);
+        let key = GenericArray::from_slice(&self.appskey);
+
+        // Initialize the cipher and process data
+        let mut ctr: Ctr128BE<Aes> = Ctr128BE::new(key, iv);
+        ctr.apply_keystream(data);
+    }
+
+    /// Processes the given data by applying the keystream derived under the given block0 preamble
+    fn apply_with_preamble(&self, preamble: u8, data: &mut [u8]) {
         // Ensure we do not encrypt more than 256 blocks, since we must only use the last byte as counter; the other
         //  bytes are defined by LoRaWAN to pin the message context
         assert!(data.len() <= 255 * 16, "Data is too long");
 
         // Build counter block 0 and prepare key
-        let block0 = self.block0(self.direction, self.address, self.frame_counter);
+        let block0 = self.block0(preamble, self.direction, self.address, self.frame_counter, self.generation);
         let iv = GenericArray::from_slice(&block0);
         let key = GenericArray::from_slice(&self.appskey);
 
@@ -111,7 +200,7 @@ where
 
     /// Generates the implicit block0, which is used to tie the message to its context
     #[inline]
-    fn block0(&self, direction: Direction, address: u32, frame_counter: u32) -> [u8; 16] {
+    fn block0(&self, preamble: u8, direction: Direction, address: u32, frame_counter: u32, generation: u8) -> [u8; 16] {
         // Destructure address and counter into bytes
         let address = address.to_le_bytes();
         let counter = frame_counter.to_le_bytes();
@@ -120,15 +209,40 @@ where
         #[rustfmt::skip]
         return [
             // Static preamble
-            0x01, 0x00, 0x00, 0x00, 0x00,
+            preamble, 0x00, 0x00, 0x00, 0x00,
             // The message direction
             direction as u8,
             // The end-device address
             address[0], address[1], address[2], address[3],
             // The frame counter
             counter[0], counter[1], counter[2], counter[3],
-            // Another static byte
+            // The key generation, so a frame from one ratchet epoch can never decrypt under another
+            generation,
+            // The first block has an index of 1
+            0x01
+        ];
+    }
+
+    /// Generates the SIV-style block0 for [`Self::apply_siv`], which ties the keystream to the on-wire MIC instead of
+    /// the frame counter
+    #[inline]
+    fn block0_siv(&self, mic: &[u8; 8], direction: Direction, address: u32, generation: u8) -> [u8; 16] {
+        // Destructure address into bytes
+        let address = address.to_le_bytes();
+
+        // Build block0
+        #[rustfmt::skip]
+        return [
+            // The on-wire MIC, computed over the header, FOpts and the plaintext payload
+            mic[0], mic[1], mic[2], mic[3], mic[4], mic[5], mic[6], mic[7],
+            // Reserved
             0x00,
+            // The message direction
+            direction as u8,
+            // The end-device address
+            address[0], address[1], address[2], address[3],
+            // The key generation, so a frame from one ratchet epoch can never decrypt under another
+            generation,
             // The first block has an index of 1
             0x01
         ];
@@ -0,0 +1,245 @@
+//! A pluggable cipher suite, so the payload keystream and the MIC primitive backing [`AesCtrBuilder`] and
+//! [`AesCmacBuilder`] can be swapped out for targets without AES hardware
+//!
+//! [`AesCtrBuilder`]: crate::crypto::aesctr::AesCtrBuilder
+//! [`AesCmacBuilder`]: crate::crypto::aescmac::AesCmacBuilder
+
+use crate::{
+    crypto::{
+        cipher::{generic_array::GenericArray, KeyIvInit, StreamCipher},
+        Aes128,
+    },
+    frame::rawframe::RawFrame,
+    Direction,
+};
+use cmac::{Cmac, Mac};
+use ctr::Ctr128BE;
+
+/// A pluggable cipher suite supplying both the payload keystream and the MIC primitive
+///
+/// # ⚠️ HAZMAT ⚠️
+/// **With this trait, it is possible to inject faulty or incompatible implementations. Faulty or incompatible
+/// implementations may result in a total and utter loss of any security.**
+///
+/// # Implementation Note
+/// Every [`Aes128`] implementation gets a [`CipherSuite`] for free via the blanket implementation below, reproducing
+/// the AES-CTR/AES-CMAC construction [`AesCtrBuilder`](crate::crypto::aesctr::AesCtrBuilder)/
+/// [`AesCmacBuilder`](crate::crypto::aescmac::AesCmacBuilder) always used, so `DefaultAes` keeps working unchanged. A
+/// target without AES hardware can instead select e.g. [`ChaCha20Poly1305Suite`] at the type level.
+pub trait CipherSuite {
+    /// The keystream generator backing this suite
+    type Keystream: StreamCipher;
+
+    /// Builds the keystream generator for the given key, tied to the message context via the suite's own
+    /// block0/nonce layout
+    fn new_keystream(
+        key: &[u8; 16],
+        direction: Direction,
+        address: u32,
+        frame_counter: u32,
+        generation: u8,
+    ) -> Self::Keystream;
+
+    /// Computes the (truncated) MIC over block0, header and payload
+    ///
+    /// # Panics
+    /// This function panics if the total message length is longer than `255` bytes.
+    fn compute_mic(
+        key: &[u8; 16],
+        direction: Direction,
+        address: u32,
+        frame_counter: u32,
+        generation: u8,
+        header: &[u8],
+        payload: &[u8],
+    ) -> [u8; RawFrame::MIC_SIZE];
+
+    /// Derives the key for the next ratchet generation from the current one
+    ///
+    /// # Implementation Note
+    /// `label` distinguishes the network and application session keys (see
+    /// [`crate::crypto::ratchet::NWKSKEY_LABEL`]/[`crate::crypto::ratchet::APPSKEY_LABEL`]) so that ratcheting never
+    /// accidentally derives the same key for both.
+    fn ratchet_key(old_key: &[u8; 16], label: u8, generation: u32) -> [u8; 16];
+}
+impl<Aes> CipherSuite for Aes
+where
+    Aes: Aes128,
+{
+    type Keystream = Ctr128BE<Aes>;
+
+    fn new_keystream(
+        key: &[u8; 16],
+        direction: Direction,
+        address: u32,
+        frame_counter: u32,
+        generation: u8,
+    ) -> Self::Keystream {
+        // The first block has an index of 1, matching `AesCtrBuilder`'s original block0 layout
+        let block0 = block0(0x01, direction, address, frame_counter, generation, 0x01);
+        let iv = GenericArray::from_slice(&block0);
+        let key = GenericArray::from_slice(key);
+        Ctr128BE::new(key, iv)
+    }
+
+    fn compute_mic(
+        key: &[u8; 16],
+        direction: Direction,
+        address: u32,
+        frame_counter: u32,
+        generation: u8,
+        header: &[u8],
+        payload: &[u8],
+    ) -> [u8; RawFrame::MIC_SIZE] {
+        let message_len = header.len().saturating_add(payload.len());
+        let message_len = u8::try_from(message_len).expect("Message is too large");
+
+        // Domain-separate the MIC from the keystream via the `0x49` preamble, matching `AesCmacBuilder`'s original
+        // block0 layout
+        let block0 = block0(0x49, direction, address, frame_counter, generation, message_len);
+        let key = GenericArray::from_slice(key);
+
+        let mut cmac: Cmac<Aes> = Cmac::new(key);
+        cmac.update(&block0);
+        cmac.update(header);
+        cmac.update(payload);
+
+        let mac = cmac.finalize().into_bytes();
+        *mac.first_chunk().expect("MAC is too short")
+    }
+
+    fn ratchet_key(old_key: &[u8; 16], label: u8, generation: u32) -> [u8; 16] {
+        crate::crypto::ratchet::ratchet::<Aes>(old_key, label, generation)
+    }
+}
+
+/// Generates the implicit block0 used by the default AES-CTR/AES-CMAC suite, tying it to the message context
+#[inline]
+fn block0(preamble: u8, direction: Direction, address: u32, frame_counter: u32, generation: u8, last: u8) -> [u8; 16] {
+    let address = address.to_le_bytes();
+    let counter = frame_counter.to_le_bytes();
+
+    #[rustfmt::skip]
+    return [
+        preamble, 0x00, 0x00, 0x00, 0x00,
+        direction as u8,
+        address[0], address[1], address[2], address[3],
+        counter[0], counter[1], counter[2], counter[3],
+        generation,
+        last,
+    ];
+}
+
+/// A cipher suite for AES-less targets: ChaCha20 for the payload keystream and Poly1305 for the MIC, analogous to how
+/// `chacha20poly1305` pairs the two for AEAD
+///
+/// # Implementation Note
+/// Loreyawen session keys are 128-bit, but ChaCha20 takes a 256-bit key; [`Self::expand_key`] expands a key by
+/// concatenating it with itself XORed against a fixed, domain-separating mask, so the two halves never collide. The
+/// nonce is 96 bits, built from the same context (direction, address, frame counter) as the default suite's block0,
+/// just without the padding a 128-bit AES block needs.
+///
+/// The Poly1305 one-time key is derived the same way the reference ChaCha20-Poly1305 AEAD construction does: it is
+/// the keystream of a dedicated block, generated under its own nonce preamble so it can never collide with the
+/// payload keystream.
+#[cfg(feature = "chacha20")]
+#[derive(Debug, Clone, Copy)]
+pub struct ChaCha20Poly1305Suite;
+#[cfg(feature = "chacha20")]
+impl ChaCha20Poly1305Suite {
+    /// The mask XORed into the second half of a 128-bit session key to expand it into the 256-bit key ChaCha20 expects
+    const KEY_EXPANSION_MASK: u8 = 0xA5;
+
+    /// Expands a 128-bit session key into the 256-bit key ChaCha20 expects
+    fn expand_key(key: &[u8; 16]) -> [u8; 32] {
+        let mut expanded = [0; 32];
+        #[allow(clippy::indexing_slicing)]
+        expanded[..16].copy_from_slice(key);
+        #[allow(clippy::indexing_slicing)]
+        for (dst, src) in expanded[16..].iter_mut().zip(key) {
+            *dst = src ^ Self::KEY_EXPANSION_MASK;
+        }
+        expanded
+    }
+
+    /// Builds the 96-bit nonce tying the keystream to its message context
+    fn nonce(preamble: u8, direction: Direction, address: u32, frame_counter: u32, generation: u8) -> [u8; 12] {
+        let address = address.to_le_bytes();
+        let counter = frame_counter.to_le_bytes();
+
+        #[rustfmt::skip]
+        return [
+            preamble, direction as u8,
+            address[0], address[1], address[2], address[3],
+            counter[0], counter[1], counter[2], counter[3],
+            generation, 0x00,
+        ];
+    }
+
+    /// The nonce preamble used to derive the next ratchet generation's key; distinct from [`Self::nonce`]'s preambles
+    /// (`0x01`/`0x49`) so a ratchet-key derivation can never collide with a payload or MIC keystream
+    const RATCHET_PREAMBLE: u8 = 0xFF;
+
+    /// Builds the 96-bit nonce used to derive the next ratchet generation's key, tied to the label and generation
+    /// instead of any particular message
+    fn ratchet_nonce(label: u8, generation: u32) -> [u8; 12] {
+        let generation = generation.to_le_bytes();
+
+        #[rustfmt::skip]
+        return [
+            Self::RATCHET_PREAMBLE, label,
+            generation[0], generation[1], generation[2], generation[3],
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+    }
+}
+#[cfg(feature = "chacha20")]
+impl CipherSuite for ChaCha20Poly1305Suite {
+    type Keystream = chacha20::ChaCha20;
+
+    fn new_keystream(
+        key: &[u8; 16],
+        direction: Direction,
+        address: u32,
+        frame_counter: u32,
+        generation: u8,
+    ) -> Self::Keystream {
+        let key = Self::expand_key(key);
+        let nonce = Self::nonce(0x01, direction, address, frame_counter, generation);
+        chacha20::ChaCha20::new((&key).into(), (&nonce).into())
+    }
+
+    fn compute_mic(
+        key: &[u8; 16],
+        direction: Direction,
+        address: u32,
+        frame_counter: u32,
+        generation: u8,
+        header: &[u8],
+        payload: &[u8],
+    ) -> [u8; RawFrame::MIC_SIZE] {
+        use poly1305::universal_hash::{KeyInit, UniversalHash};
+
+        // Derive the one-time Poly1305 key from a dedicated keystream, domain-separated from the payload keystream
+        // by its own nonce preamble
+        let key = Self::expand_key(key);
+        let nonce = Self::nonce(0x49, direction, address, frame_counter, generation);
+        let mut poly1305_key = [0; 32];
+        chacha20::ChaCha20::new((&key).into(), (&nonce).into()).apply_keystream(&mut poly1305_key);
+
+        let mut poly1305 = poly1305::Poly1305::new((&poly1305_key).into());
+        poly1305.update_padded(header);
+        poly1305.update_padded(payload);
+
+        let tag = poly1305.finalize();
+        *tag.first_chunk().expect("tag is too short")
+    }
+
+    fn ratchet_key(old_key: &[u8; 16], label: u8, generation: u32) -> [u8; 16] {
+        let key = Self::expand_key(old_key);
+        let nonce = Self::ratchet_nonce(label, generation);
+        let mut derived = [0; 32];
+        chacha20::ChaCha20::new((&key).into(), (&nonce).into()).apply_keystream(&mut derived);
+        *derived.first_chunk().expect("derived key is too short")
+    }
+}
@@ -14,9 +14,19 @@ pub type DefaultAes = ();
 #[cfg(feature = "aes")]
 pub type DefaultAes = aes::Aes128;
 
+/// The default frame counter value at which [`crate::frame::builderseal`]'s `pack()` ratchets the session to the
+/// next key generation, rather than letting the counter run to exhaustion
+pub const DEFAULT_RATCHET_THRESHOLD: u32 = u32::MAX - 0xFFFF;
+
 /// A frame builder
+///
+/// # Implementation Note
+/// `RATCHET_THRESHOLD` is the frame counter value at which [`pack()`](crate::frame::builderseal) ratchets the
+/// session to the next key generation instead of letting the counter run towards exhaustion; it defaults to
+/// [`DEFAULT_RATCHET_THRESHOLD`], but a caller that wants to rekey earlier (or never, by passing `u32::MAX`) can
+/// override it explicitly, e.g. `FrameBuilder::<_, _, _, _, 1_000>::new(session)`.
 #[derive(Debug, Clone, Copy)]
-pub struct FrameBuilder<Aes, Session = (), Direction = (), State = ()> {
+pub struct FrameBuilder<Aes, Session = (), Direction = (), State = (), const RATCHET_THRESHOLD: u32 = DEFAULT_RATCHET_THRESHOLD> {
     /// A type reference to the underlying AES implementation
     pub(in crate::frame) aes: PhantomData<Aes>,
     /// The underlying session state
@@ -26,15 +36,15 @@ pub struct FrameBuilder<Aes, Session = (), Direction = (), State = ()> {
     /// The transformation state
     pub(in crate::frame) state: State,
 }
-impl<Aes> FrameBuilder<Aes> {
+impl<Aes, const RATCHET_THRESHOLD: u32> FrameBuilder<Aes, (), (), (), RATCHET_THRESHOLD> {
     /// Create a new frame builder with the given session
-    pub const fn new<Session>(session: Session) -> FrameBuilder<Aes, Session> {
+    pub const fn new<Session>(session: Session) -> FrameBuilder<Aes, Session, (), (), RATCHET_THRESHOLD> {
         FrameBuilder { aes: PhantomData, session, direction: (), state: () }
     }
 }
-impl<Aes, Session> FrameBuilder<Aes, Session> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32> FrameBuilder<Aes, Session, (), (), RATCHET_THRESHOLD> {
     /// Set the frame direction (Uplink or Downlink)
-    pub fn set_direction(self, direction: Direction) -> FrameBuilder<Aes, Session, Direction> {
+    pub fn set_direction(self, direction: Direction) -> FrameBuilder<Aes, Session, Direction, (), RATCHET_THRESHOLD> {
         let Self { aes, session, state, .. } = self;
         FrameBuilder { aes, session, direction, state }
     }
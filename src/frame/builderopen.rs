@@ -2,13 +2,18 @@
 
 use crate::crypto::aescmac::AesCmacBuilder;
 use crate::crypto::aesctr::AesCtrBuilder;
-use crate::crypto::Aes128;
+use crate::crypto::ratchet::{APPSKEY_LABEL, NWKSKEY_LABEL};
+use crate::crypto::suite::CipherSuite;
 use crate::frame::builder::FrameBuilder;
 use crate::frame::rawframe::RawFrame;
 use crate::frame::MAX_PAYLOAD_SIZE;
 use crate::{Direction, SessionState};
 use core::ops::Deref;
 
+/// The maximum amount of generations we are willing to ratchet forward in a single call, to bound the work done for
+/// a frame with a bogus generation byte
+const MAX_RATCHET_STEPS: u32 = 0xFF;
+
 /// A sealed intermediate frame
 #[derive(Debug, Clone, Copy)]
 pub struct SealedFrame {
@@ -32,9 +37,12 @@ pub struct PlaintextFrame {
 }
 
 // Implement decryption logic
-impl<Aes, Session> FrameBuilder<Aes, Session, Direction> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32> FrameBuilder<Aes, Session, Direction, (), RATCHET_THRESHOLD> {
     /// Parses a raw frame
-    pub fn set_frame(self, frame: &[u8]) -> Option<FrameBuilder<Aes, Session, Direction, SealedFrame>> {
+    pub fn set_frame(
+        self,
+        frame: &[u8],
+    ) -> Option<FrameBuilder<Aes, Session, Direction, SealedFrame, RATCHET_THRESHOLD>> {
         // Parse frame
         let raw = RawFrame::parse(frame)?;
         let frame = SealedFrame { raw };
@@ -44,7 +52,9 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction> {
         Some(FrameBuilder { aes, session, direction, state: frame })
     }
 }
-impl<Aes, Session> FrameBuilder<Aes, Session, Direction, SealedFrame> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32>
+    FrameBuilder<Aes, Session, Direction, SealedFrame, RATCHET_THRESHOLD>
+{
     /// This is a reserved frame counter that must not be used by frames, so implementations can use it as marker value
     /// to e.g. mark a session as exhausted
     ///
@@ -55,19 +65,51 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, SealedFrame> {
     /// process any more messages, as there are no more valid frame counter values left.
     pub(in crate::frame) const RESERVED_FRAME_COUNTER: u32 = u32::MAX;
 
+    /// Ratchets the session forward until its key generation matches the given generation byte from the frame, or
+    /// gives up if that would take [`MAX_RATCHET_STEPS`] steps or more
+    ///
+    /// # Implementation Note
+    /// Ratcheting forward resets both frame counters, as the new generation starts counting from zero again. Sessions
+    /// that do not implement [`SessionState::ratchet`] never advance their generation, so this is a no-op for them as
+    /// long as the frame was sealed under generation `0`.
+    fn ratchet_to_generation(session: &mut Session, frame_generation: u8) -> Option<()>
+    where
+        Session: SessionState,
+        Aes: CipherSuite,
+    {
+        for _ in 0..MAX_RATCHET_STEPS {
+            #[allow(clippy::cast_possible_truncation, reason = "generation is ratcheted in lockstep on both sides")]
+            if session.generation() as u8 == frame_generation {
+                return Some(());
+            }
+
+            // The frame is sealed under a later generation than ours; derive the next one and try again
+            let next_generation = session.generation().wrapping_add(1);
+            let nwkskey = Aes::ratchet_key(session.nwkskey(), NWKSKEY_LABEL, next_generation);
+            let appskey = Aes::ratchet_key(session.appskey(), APPSKEY_LABEL, next_generation);
+            session.ratchet(nwkskey, appskey, next_generation);
+            session.set_frame_counter(0, Direction::Uplink);
+            session.set_frame_counter(0, Direction::Downlink);
+        }
+
+        // The frame generation never matched; give up rather than ratcheting indefinitely
+        None
+    }
+
     /// Validates the frame against the session and decrypts the plaintext
     ///
     /// # Implementation Details
     /// This step performs the following session-specific message validation and decryption steps in this order:
     /// 1. Validate the address to see if the message is really addressed to us
-    /// 2. Attempt to recover the frame counter and make sure it does not exhaust the session
-    /// 3. Validate the MIC over header and payload
-    /// 4. Decrypt the payload
-    /// 4. Commit the frame counter of the message to the message state
-    pub fn unpack(mut self) -> Option<FrameBuilder<Aes, Session, Direction, PlaintextFrame>>
+    /// 2. Ratchet the session forward if the frame was sealed under a newer key generation than ours
+    /// 3. Attempt to recover the frame counter and make sure it does not exhaust the session
+    /// 4. Validate the MIC over header and payload
+    /// 5. Decrypt the payload
+    /// 6. Commit the frame counter of the message to the message state
+    pub fn unpack(mut self) -> Option<FrameBuilder<Aes, Session, Direction, PlaintextFrame, RATCHET_THRESHOLD>>
     where
         Session: SessionState,
-        Aes: Aes128,
+        Aes: CipherSuite,
     {
         // Validate address
         let device_address = self.session.device_address();
@@ -76,6 +118,11 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, SealedFrame> {
             return None;
         };
 
+        // Ratchet the session forward if the frame was sealed under a newer key generation than the one we have; this
+        // tolerates the bump arriving out of order, e.g. because an earlier frame of the new generation was lost
+        Self::ratchet_to_generation(&mut self.session, self.state.raw.generation())?;
+        let generation = self.state.raw.generation();
+
         // Recover and validate frame counter
         let maybe_frame_counter = {
             // Recover the most-likely frame counter relative to the session state
@@ -94,6 +141,7 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, SealedFrame> {
             .set_direction(self.direction)
             .set_address(self.state.raw.address())
             .set_frame_counter(frame_counter)
+            .set_generation(generation)
             .verify(self.state.raw.header(), self.state.raw.payload(), self.state.raw.mic());
         let true = mic_valid else {
             // Reject invalid MICs
@@ -106,6 +154,7 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, SealedFrame> {
             .set_direction(self.direction)
             .set_address(self.state.raw.address())
             .set_frame_counter(frame_counter)
+            .set_generation(generation)
             .apply(self.state.raw.payload_mut());
 
         // Commit next frame counter
@@ -147,7 +196,7 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, SealedFrame> {
         }
     }
 }
-impl<Aes, Session> FrameBuilder<Aes, Session, Direction, PlaintextFrame> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32> FrameBuilder<Aes, Session, Direction, PlaintextFrame, RATCHET_THRESHOLD> {
     /// Gets the frame counter
     pub fn frame_counter(&self) -> u32 {
         self.state.frame_counter
@@ -163,7 +212,9 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, PlaintextFrame> {
         self.state.frame_port
     }
 }
-impl<Aes, Session> Deref for FrameBuilder<Aes, Session, Direction, PlaintextFrame> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32> Deref
+    for FrameBuilder<Aes, Session, Direction, PlaintextFrame, RATCHET_THRESHOLD>
+{
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
@@ -2,7 +2,8 @@
 
 use crate::crypto::aescmac::AesCmacBuilder;
 use crate::crypto::aesctr::AesCtrBuilder;
-use crate::crypto::Aes128;
+use crate::crypto::ratchet::{APPSKEY_LABEL, NWKSKEY_LABEL};
+use crate::crypto::suite::CipherSuite;
 use crate::frame::builder::FrameBuilder;
 use crate::frame::rawframe::RawFrame;
 use crate::frame::MAX_MESSAGE_SIZE;
@@ -49,12 +50,15 @@ impl IntoIterator for SealedFrame {
 }
 
 // Implement encryption logic
-impl<Aes, Session> FrameBuilder<Aes, Session, Direction> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32> FrameBuilder<Aes, Session, Direction, (), RATCHET_THRESHOLD> {
     /// Sets and parses the frame
     ///
     /// # Panics
     /// This function panics if the payload is greater than [`MAX_PAYLOAD_SIZE`](crate::frame::MAX_PAYLOAD_SIZE).
-    pub fn set_plaintext(self, plaintext: &[u8]) -> FrameBuilder<Aes, Session, Direction, IntermediateFrame> {
+    pub fn set_plaintext(
+        self,
+        plaintext: &[u8],
+    ) -> FrameBuilder<Aes, Session, Direction, IntermediateFrame, RATCHET_THRESHOLD> {
         // Create frame
         let raw = RawFrame::new(plaintext);
         let frame = IntermediateFrame { raw };
@@ -64,7 +68,9 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction> {
         FrameBuilder { aes, session, direction, state: frame }
     }
 }
-impl<Aes, Session> FrameBuilder<Aes, Session, Direction, IntermediateFrame> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32>
+    FrameBuilder<Aes, Session, Direction, IntermediateFrame, RATCHET_THRESHOLD>
+{
     /// Sets the `FCtrl` byte
     pub fn set_frame_ctrl(mut self, frame_ctrl: u8) -> Self {
         self.state.raw.set_frame_ctrl(frame_ctrl);
@@ -77,22 +83,66 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, IntermediateFrame> {
         self
     }
 
+    /// Ratchets the session to the next key generation if the frame counter for the configured direction is
+    /// approaching exhaustion
+    ///
+    /// # Implementation Note
+    /// Sessions that do not implement [`SessionState::ratchet`] stay on generation `0` forever; [`SessionState::ratchet`]'s
+    /// default implementation is a no-op, so this only resets the frame counters once the generation actually
+    /// advanced. Otherwise the same `nwkskey`/`appskey` would be reused under counters that restart at `0`, which is
+    /// an immediate keystream/MIC nonce reuse. A session that does not override [`SessionState::ratchet`] therefore
+    /// keeps counting towards exhaustion and still panics in [`Self::pack`], same as before this was added.
+    fn ratchet_if_exhausted(&mut self)
+    where
+        Session: SessionState,
+        Aes: CipherSuite,
+    {
+        if self.session.frame_counter(self.direction) < RATCHET_THRESHOLD {
+            return;
+        }
+
+        let generation = self.session.generation().wrapping_add(1);
+        let nwkskey = Aes::ratchet_key(self.session.nwkskey(), NWKSKEY_LABEL, generation);
+        let appskey = Aes::ratchet_key(self.session.appskey(), APPSKEY_LABEL, generation);
+        self.session.ratchet(nwkskey, appskey, generation);
+
+        // Only reset the counters if the ratchet actually advanced the generation; otherwise the frame counter must
+        // keep counting towards exhaustion under the same keys instead of restarting at 0
+        if self.session.generation() == generation {
+            self.session.set_frame_counter(0, Direction::Uplink);
+            self.session.set_frame_counter(0, Direction::Downlink);
+        }
+    }
+
     /// Encrypts the frame updates the session accordingly
     ///
+    /// # Implementation Note
+    /// If the frame counter for the configured direction has reached `RATCHET_THRESHOLD` (see
+    /// [`FrameBuilder`](crate::frame::builder::FrameBuilder)), the session is ratcheted to the next key generation
+    /// before the frame is sealed, and the counter restarts from `0` under the new keys, so a long-lived session
+    /// whose `Session` implements [`SessionState::ratchet`] never hard-fails on exhaustion.
+    ///
     /// # Panics
-    /// This function panics if the frame counter for the configured direction is exhausted.
-    pub fn pack(mut self) -> FrameBuilder<Aes, Session, Direction, SealedFrame>
+    /// This function panics if the frame counter for the configured direction is exhausted and `Session` does not
+    /// implement [`SessionState::ratchet`].
+    pub fn pack(mut self) -> FrameBuilder<Aes, Session, Direction, SealedFrame, RATCHET_THRESHOLD>
     where
         Session: SessionState,
-        Aes: Aes128,
+        Aes: CipherSuite,
     {
-        // Get device address and next frame counter
+        // Ratchet to the next key generation if the counter is approaching exhaustion
+        self.ratchet_if_exhausted();
+
+        // Get device address, next frame counter and generation
         let address = self.session.device_address();
         let next_frame_counter = self.session.frame_counter(self.direction);
+        #[allow(clippy::cast_possible_truncation, reason = "generation is ratcheted in lockstep on both sides")]
+        let generation = self.session.generation() as u8;
 
         // Assemble frame
         self.state.raw.set_address(address);
         self.state.raw.set_frame_counter_lsbs(next_frame_counter as u16);
+        self.state.raw.set_generation(generation);
 
         // Encrypt payload
         let appskey = self.session.appskey();
@@ -100,6 +150,7 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, IntermediateFrame> {
             .set_direction(self.direction)
             .set_address(address)
             .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
             .apply(self.state.raw.payload_mut());
 
         // Compute MIC
@@ -108,6 +159,7 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, IntermediateFrame> {
             .set_direction(self.direction)
             .set_address(address)
             .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
             .compute(self.state.raw.header(), self.state.raw.payload());
 
         // Commit next frame counter
@@ -123,7 +175,9 @@ impl<Aes, Session> FrameBuilder<Aes, Session, Direction, IntermediateFrame> {
         FrameBuilder { aes, session, direction, state: output }
     }
 }
-impl<Aes, Session> Deref for FrameBuilder<Aes, Session, Direction, SealedFrame> {
+impl<Aes, Session, const RATCHET_THRESHOLD: u32> Deref
+    for FrameBuilder<Aes, Session, Direction, SealedFrame, RATCHET_THRESHOLD>
+{
     type Target = SealedFrame;
 
     fn deref(&self) -> &Self::Target {
@@ -0,0 +1,379 @@
+//! A second logical channel for TLV-encoded control/MAC commands (rekey requests, counter resync, link-check, ...),
+//! encrypted with the network session key instead of the application session key, matching LoRaWAN's separation
+//! between FOpts and the application payload
+
+use crate::{
+    crypto::{
+        mic::MicBuilder,
+        ratchet::{self, APPSKEY_LABEL, NWKSKEY_LABEL},
+        stream::CipherstreamBuilder,
+        Aes128,
+    },
+    frame::{
+        raw::{CipherSuite, RawFrame},
+        MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE,
+    },
+    Direction, SessionState,
+};
+use core::{marker::PhantomData, ops::Deref};
+
+/// The maximum amount of generations we are willing to ratchet forward in a single call, to bound the work done for
+/// a frame with a bogus generation byte
+const MAX_RATCHET_STEPS: u32 = 0xFF;
+
+/// A builder to encrypt and seal a set of control commands into a control frame
+#[derive(Debug)]
+pub struct ControlFrameBuilder<Session, Aes> {
+    /// The underlying session state
+    session: Session,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Session, Aes> ControlFrameBuilder<Session, Aes> {
+    /// Create a new builder for the given session and implementation
+    pub fn new(session: Session) -> Self {
+        Self { session, _aes: PhantomData }
+    }
+
+    /// Set the direction of the associated message
+    pub fn set_direction(self, direction: Direction) -> ControlFrameBuilderWithDirection<Session, Aes> {
+        ControlFrameBuilderWithDirection { session: self.session, direction, _aes: self._aes }
+    }
+}
+
+/// A builder to encrypt and seal a set of control commands into a control frame
+#[derive(Debug)]
+pub struct ControlFrameBuilderWithDirection<Session, Aes> {
+    /// The underlying session state
+    session: Session,
+    /// The direction of the associated message
+    direction: Direction,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Session, Aes> ControlFrameBuilderWithDirection<Session, Aes>
+where
+    Session: SessionState,
+    Aes: Aes128,
+{
+    /// Sets the payload (usually TLV-encoded commands, as walked by [`CommandIterator`]), encrypts it with the
+    /// network session key and updates the session accordingly
+    ///
+    /// # Panics
+    /// This function panics if the payload is greater than [`MAX_PAYLOAD_SIZE`](crate::frame::MAX_PAYLOAD_SIZE).
+    /// This function also panics if the frame counter for the configured direction is exhaused.
+    pub fn set_payload(mut self, payload: &[u8]) -> ControlFrame {
+        // Get device address, next frame counter and generation
+        let address = self.session.device_address();
+        let next_frame_counter = self.session.frame_counter(self.direction);
+        let generation = self.session.generation() as u8;
+
+        // Assemble frame, marking it as a control frame
+        let mut raw = RawFrame::new(payload);
+        raw.set_cipher_suite(CipherSuite::Control);
+        raw.set_address(address);
+        raw.set_frame_counter_lsbs(next_frame_counter as u16);
+        raw.set_generation(generation);
+
+        // Encrypt payload with the network session key instead of the application session key
+        let nwkskey = self.session.nwkskey();
+        CipherstreamBuilder::<Aes>::new(nwkskey)
+            .set_direction(self.direction)
+            .set_address(address)
+            .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
+            .apply(raw.payload_mut());
+
+        // Compute MIC
+        *raw.mic_mut() = MicBuilder::<Aes>::new(nwkskey)
+            .set_direction(self.direction)
+            .set_address(address)
+            .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
+            .compute(raw.header(), raw.fopts(), raw.payload());
+
+        // Commit next frame counter
+        let next_frame_counter = next_frame_counter.checked_add(1).expect("frame counter is exhaused");
+        self.session.set_frame_counter(next_frame_counter, self.direction);
+
+        // Init next step
+        let (frame, frame_len) = raw.into_frame();
+        ControlFrame { frame, frame_len }
+    }
+}
+
+/// The encrypted, final control frame
+#[derive(Debug, Clone, Copy)]
+pub struct ControlFrame {
+    /// The frame data buffer
+    frame: [u8; MAX_MESSAGE_SIZE],
+    /// The amount of bytes within the frame data buffer
+    frame_len: usize,
+}
+impl Deref for ControlFrame {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        // Note: The frame length is assumed to be valid
+        #[allow(clippy::indexing_slicing)]
+        &self.frame[..self.frame_len]
+    }
+}
+
+/// A builder to open a control frame, verifying the MIC and decrypting the commands with the network session key
+#[derive(Debug)]
+pub struct ControlFrameOpener<Session, Aes> {
+    /// The underlying session state
+    session: Session,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Session, Aes> ControlFrameOpener<Session, Aes> {
+    /// Create a new opener for the given session and implementation
+    pub fn new(session: Session) -> Self {
+        Self { session, _aes: PhantomData }
+    }
+
+    /// Set the direction of the associated message
+    pub fn set_direction(self, direction: Direction) -> ControlFrameOpenerWithDirection<Session, Aes> {
+        ControlFrameOpenerWithDirection { session: self.session, direction, _aes: self._aes }
+    }
+}
+
+/// A builder to open a control frame, verifying the MIC and decrypting the commands with the network session key
+#[derive(Debug)]
+pub struct ControlFrameOpenerWithDirection<Session, Aes> {
+    /// The underlying session state
+    session: Session,
+    /// The direction of the associated message
+    direction: Direction,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Session, Aes> ControlFrameOpenerWithDirection<Session, Aes> {
+    /// Sets and parses the frame
+    ///
+    /// # Implementation Note
+    /// This rejects a frame that is not marked as a control frame, i.e. whose cipher suite is not
+    /// [`CipherSuite::Control`].
+    pub fn set_frame(self, frame: &[u8]) -> Option<ControlFrameOpenerWithFrame<Session, Aes>> {
+        let raw = RawFrame::parse(frame)?;
+        let CipherSuite::Control = raw.cipher_suite() else {
+            // This is not a control frame
+            return None;
+        };
+
+        Some(ControlFrameOpenerWithFrame { session: self.session, direction: self.direction, raw, _aes: self._aes })
+    }
+}
+
+/// A builder to open a control frame, verifying the MIC and decrypting the commands with the network session key
+#[derive(Debug)]
+pub struct ControlFrameOpenerWithFrame<Session, Aes> {
+    /// The underlying session state
+    session: Session,
+    /// The direction of the associated message
+    direction: Direction,
+    /// The raw frame
+    raw: RawFrame,
+    /// The underlying implementation
+    _aes: PhantomData<Aes>,
+}
+impl<Session, Aes> ControlFrameOpenerWithFrame<Session, Aes>
+where
+    Session: SessionState,
+    Aes: Aes128,
+{
+    /// Validates the frame against the session and decrypts the commands
+    ///
+    /// # Implementation Details
+    /// This step performs the following session-specific message validation and decryption steps in this order:
+    /// 1. Validate the address to see if the message is really addressed to us
+    /// 2. Ratchet the session forward if the frame was sealed under a newer key generation than ours
+    /// 3. Reconstruct the full frame counter relative to the session's frame counter for this direction (one past
+    ///    the highest counter accepted so far), tolerating reordering within [`SessionState::REPLAY_WINDOW_WIDTH`]
+    /// 4. Validate the MIC (over header, `FOpts` and payload) against the reconstructed counter
+    /// 5. Decrypt the commands
+    /// 6. Commit the frame counter of the message to the session state, if it advances it
+    pub fn unpack(mut self) -> Option<CommandBuffer> {
+        // Validate address
+        let device_address = self.session.device_address();
+        let true = self.raw.address() == device_address else {
+            // Apparently the message is not for us
+            return None;
+        };
+
+        // Ratchet the session forward if the frame was sealed under a newer key generation than the one we have; this
+        // tolerates the bump arriving out of order, e.g. because an earlier frame of the new generation was lost
+        Self::ratchet_to_generation(&mut self.session, self.raw.generation())?;
+
+        // Reconstruct and validate the frame counter
+        let nwkskey = *self.session.nwkskey();
+        let frame_counter = Self::recover_frame_counter(&mut self.session, &nwkskey, self.direction, &self.raw)?;
+
+        // Decrypt payload with the network session key
+        let generation = self.raw.generation();
+        CipherstreamBuilder::<Aes>::new(&nwkskey)
+            .set_direction(self.direction)
+            .set_address(self.raw.address())
+            .set_frame_counter(frame_counter)
+            .set_generation(generation)
+            .apply(self.raw.payload_mut());
+
+        // Commit the next frame counter, but only if the accepted frame actually advances it; an accepted
+        // out-of-order frame must not push the session's high-water mark backwards
+        let next_frame_counter = frame_counter.saturating_add(1);
+        if next_frame_counter > self.session.frame_counter(self.direction) {
+            self.session.set_frame_counter(next_frame_counter, self.direction);
+        }
+
+        // Init next step
+        let (commands, commands_len) = self.raw.into_payload();
+        Some(CommandBuffer { commands, commands_len })
+    }
+
+    /// Ratchets the session forward until its key generation matches the given generation byte from the frame, or
+    /// gives up if that would take [`MAX_RATCHET_STEPS`] steps or more
+    ///
+    /// # Implementation Note
+    /// Ratcheting forward resets both frame counters, as the new generation starts counting from zero again. Sessions
+    /// that do not implement [`SessionState::ratchet`] never advance their generation, so this is a no-op for them as
+    /// long as the frame was sealed under generation `0`.
+    fn ratchet_to_generation(session: &mut Session, frame_generation: u8) -> Option<()> {
+        for _ in 0..MAX_RATCHET_STEPS {
+            if session.generation() as u8 == frame_generation {
+                return Some(());
+            }
+
+            // The frame is sealed under a later generation than ours; derive the next one and try again
+            let next_generation = session.generation().wrapping_add(1);
+            let nwkskey = ratchet::ratchet::<Aes>(session.nwkskey(), NWKSKEY_LABEL, next_generation);
+            let appskey = ratchet::ratchet::<Aes>(session.appskey(), APPSKEY_LABEL, next_generation);
+            session.ratchet(nwkskey, appskey, next_generation);
+            session.set_frame_counter(0, Direction::Uplink);
+            session.set_frame_counter(0, Direction::Downlink);
+        }
+
+        // The frame generation never matched; give up rather than ratcheting indefinitely
+        None
+    }
+
+    /// Reconstructs the full 32-bit frame counter relative to the session's frame counter `high` for this direction
+    /// (one past the highest counter accepted so far), validates the MIC against it, and consults the session's
+    /// anti-replay window
+    ///
+    /// # Security Considerations
+    /// The frame only transmits the 16 least-significant bits of the counter. We recombine them with the high bits of
+    /// `high`, as well as the epoch immediately before and after it, to resolve a 16-bit wraparound in either
+    /// direction, and pick whichever candidate is numerically closest to `high`. A candidate that does not advance
+    /// `high` is only accepted if it still falls within [`SessionState::REPLAY_WINDOW_WIDTH`]; anything older is
+    /// rejected outright. The MIC is validated, and the replay window is only ever consulted (and updated), after
+    /// this, so a forged frame can neither desynchronize recovery nor poison the replay state.
+    fn recover_frame_counter(
+        session: &mut Session,
+        nwkskey: &[u8; 16],
+        direction: Direction,
+        raw: &RawFrame,
+    ) -> Option<u32> {
+        // Recombine the high bits of the current, previous and next epoch with the transmitted low bits, and pick
+        // whichever candidate lies closest to `high`
+        let high = session.frame_counter(direction);
+        let recv_lsb = u32::from(raw.frame_counter_lsbs());
+        let epoch = high & 0xFFFF_0000;
+        let candidates = [
+            epoch.checked_sub(0x1_0000).map(|epoch| epoch | recv_lsb),
+            Some(epoch | recv_lsb),
+            epoch.checked_add(0x1_0000).map(|epoch| epoch | recv_lsb),
+        ];
+        let candidate = candidates.into_iter().flatten().min_by_key(|candidate| candidate.abs_diff(high))?;
+
+        // Reject candidates that neither reach (or advance) `high` nor fall within the replay window
+        //
+        // Note: `candidate >= high` (not `>`) is required so that the very first frame of a fresh session
+        // (`high == 0`, `candidate == 0`) is treated as reaching the expected counter rather than as a replay that
+        // has to fall within `REPLAY_WINDOW_WIDTH`, which defaults to `0` and would otherwise reject it forever.
+        let in_window = match candidate >= high {
+            true => true,
+            false => high.saturating_sub(candidate) < Session::REPLAY_WINDOW_WIDTH,
+        };
+        let true = in_window else {
+            return None;
+        };
+
+        // Only trust the candidate once the MIC actually verifies against it
+        let mic_valid = MicBuilder::<Aes>::new(nwkskey)
+            .set_direction(direction)
+            .set_address(raw.address())
+            .set_frame_counter(candidate)
+            .set_generation(raw.generation())
+            .verify(raw.header(), raw.fopts(), raw.payload(), raw.mic());
+        let true = mic_valid else {
+            return None;
+        };
+
+        // Finally, reject already-seen counters within the window
+        session.check_and_set_replay(candidate, direction).then_some(candidate)
+    }
+}
+
+/// The decrypted, TLV-encoded command payload of a control frame
+#[derive(Debug, Clone, Copy)]
+pub struct CommandBuffer {
+    /// The decrypted command buffer
+    commands: [u8; MAX_PAYLOAD_SIZE],
+    /// The amount of bytes within the command buffer
+    commands_len: usize,
+}
+impl CommandBuffer {
+    /// Returns an iterator over the individual commands in this buffer
+    pub fn iter(&self) -> CommandIterator<'_> {
+        CommandIterator::new(self)
+    }
+}
+impl Deref for CommandBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        // Note: The command buffer length is assumed to be valid
+        #[allow(clippy::indexing_slicing, reason = "Length is assumed to be valid")]
+        &self.commands[..self.commands_len]
+    }
+}
+
+/// A single TLV-encoded control/MAC command
+#[derive(Debug, Clone, Copy)]
+pub struct Command<'a> {
+    /// The command identifier
+    pub cid: u8,
+    /// The command arguments
+    pub args: &'a [u8],
+}
+
+/// An iterator that walks a decrypted control payload and yields the commands within, stopping gracefully (rather
+/// than panicking) on a command whose declared argument length overruns the remaining buffer
+#[derive(Debug, Clone)]
+pub struct CommandIterator<'a> {
+    /// The not yet consumed remainder of the command buffer
+    remaining: &'a [u8],
+}
+impl<'a> CommandIterator<'a> {
+    /// Creates a new iterator over the given command buffer
+    fn new(commands: &'a [u8]) -> Self {
+        Self { remaining: commands }
+    }
+}
+impl<'a> Iterator for CommandIterator<'a> {
+    type Item = Command<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Split off the command identifier and the declared argument length
+        let (&cid, remaining) = self.remaining.split_first()?;
+        let (&len, remaining) = remaining.split_first()?;
+
+        // Split off the arguments; this rejects (by stopping iteration) a declared length that overruns the buffer,
+        // instead of panicking
+        let (args, remaining) = remaining.split_at_checked(usize::from(len))?;
+        self.remaining = remaining;
+        Some(Command { cid, args })
+    }
+}
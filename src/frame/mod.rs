@@ -3,11 +3,16 @@
 pub mod builder;
 pub mod builderopen;
 pub mod builderseal;
+pub mod control;
+pub mod plaintext;
 pub mod raw;
+pub mod rawframe;
+pub mod sealed;
 
 use crate::frame::raw::RawFrame;
 
 /// The maximum message size
 pub const MAX_MESSAGE_SIZE: usize = 255;
 /// The maximum size of a payload
-pub const MAX_PAYLOAD_SIZE: usize = MAX_MESSAGE_SIZE - RawFrame::HEADER_SIZE - RawFrame::MIC_SIZE;
+pub const MAX_PAYLOAD_SIZE: usize =
+    MAX_MESSAGE_SIZE - RawFrame::HEADER_SIZE - RawFrame::MAX_FOPTS_SIZE - RawFrame::MIC_SIZE;
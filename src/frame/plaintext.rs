@@ -1,12 +1,36 @@
 //! A builder to validate and decrypt a sealed frame into a plaintext
 
+#[cfg(feature = "header-protection")]
+use crate::crypto::header_protection;
 use crate::{
     crypto::{mic::MicBuilder, stream::CipherstreamBuilder, Aes128},
-    frame::{raw::RawFrame, MAX_PAYLOAD_SIZE},
-    Direction, SessionState,
+    frame::{
+        raw::{CipherSuite, RawFrame},
+        MAX_PAYLOAD_SIZE,
+    },
+    Direction, SessionRefMut, SessionState, SessionStore,
 };
 use core::{marker::PhantomData, ops::Deref};
 
+/// Parses just enough of a sealed frame to look up the right session in `store`, and then runs the full
+/// unpack/MIC-verify/counter-update logic against it
+///
+/// # Implementation Note
+/// This is the entry point for a gateway or network server that handles many end-devices and does not know upfront
+/// which device a given frame came from. It returns the device address alongside the plaintext, since the caller
+/// cannot be assumed to already know it.
+pub fn dispatch<Store, Aes>(store: &mut Store, direction: Direction, frame: &[u8]) -> Option<(u32, Plaintext)>
+where
+    Store: SessionStore,
+    Aes: Aes128,
+{
+    let device_address = RawFrame::peek_address(frame)?;
+    let session = SessionRefMut::new(store.session_mut(device_address)?);
+
+    let plaintext = PlaintextBuilder::<_, Aes>::new(session).set_direction(direction).set_frame(frame)?.unpack()?;
+    Some((device_address, plaintext))
+}
+
 /// A builder to validate and decrypt a sealed frame into a plaintext
 #[derive(Debug)]
 pub struct PlaintextBuilder<Session, Aes> {
@@ -39,9 +63,24 @@ pub struct PlaintextBuilderWithDirection<Session, Aes> {
 }
 impl<Session, Aes> PlaintextBuilderWithDirection<Session, Aes> {
     /// Sets and parses the frame
+    ///
+    /// # Implementation Note
+    /// This rejects a frame sealed with a cipher suite other than [`CipherSuite::AesCtrAesCmac`], as this is the only
+    /// suite this builder knows how to unpack.
     pub fn set_frame(self, frame: &[u8]) -> Option<PlaintextBuilderWithFrame<Session, Aes>> {
         let raw = RawFrame::parse(frame)?;
-        Some(PlaintextBuilderWithFrame { session: self.session, direction: self.direction, raw, _aes: self._aes })
+        let CipherSuite::AesCtrAesCmac = raw.cipher_suite() else {
+            // We do not (yet) support any other cipher suite
+            return None;
+        };
+
+        Some(PlaintextBuilderWithFrame {
+            session: self.session,
+            direction: self.direction,
+            raw,
+            max_forward_gap: u32::MAX,
+            _aes: self._aes,
+        })
     }
 }
 
@@ -54,6 +93,8 @@ pub struct PlaintextBuilderWithFrame<Session, Aes> {
     direction: Direction,
     /// The raw frame
     raw: RawFrame,
+    /// The maximum amount the recovered frame counter may lie ahead of the expected counter
+    max_forward_gap: u32,
     /// The underlying implementation
     _aes: PhantomData<Aes>,
 }
@@ -72,15 +113,31 @@ where
     /// are no more valid frame counter values left.
     pub(in crate::frame) const RESERVED_FRAME_COUNTER: u32 = u32::MAX;
 
+    /// Limits how far ahead of the expected frame counter a recovered counter may lie, rejecting anything further
+    /// ahead instead of accepting it
+    ///
+    /// # Implementation Note
+    /// This bounds how far a single forged (or genuinely very out-of-order) frame can fast-forward the session; it
+    /// defaults to [`u32::MAX`], i.e. no limit.
+    pub fn set_max_forward_gap(mut self, max_forward_gap: u32) -> Self {
+        self.max_forward_gap = max_forward_gap;
+        self
+    }
+
     /// Validates the frame against the session and decrypts the plaintext
     ///
     /// # Implementation Details
     /// This step performs the following session-specific message validation and decryption steps in this order:
     /// 1. Validate the address to see if the message is really addressed to us
-    /// 2. Attempt to recover the frame counter and make sure it does not exhaust the session
-    /// 3. Validate the MIC over header and payload
-    /// 4. Decrypt the payload
-    /// 4. Commit the frame counter of the message to the message state
+    /// 2. Reconstruct the full frame counter relative to the counter expected next for this direction, rejecting a
+    ///    gap wider than [`Self::set_max_forward_gap`]
+    /// 3. Validate the MIC over header, `FOpts` and payload
+    /// 4. Decrypt `FOpts` and the payload
+    /// 5. Commit the next frame counter to the session state
+    ///
+    /// If the frame is marked via [`RawFrame::is_siv`], steps 3 and 4 are reordered (decrypt-then-verify instead of
+    /// verify-then-decrypt) to make the payload keystream nonce-misuse-resistant; see [`RawFrame::set_siv`] and
+    /// [`crate::crypto::stream::CipherstreamBuilderWithGeneration::apply_siv`].
     #[allow(non_contiguous_range_endpoints)]
     #[allow(clippy::missing_panics_doc)]
     pub fn unpack(mut self) -> Option<Plaintext> {
@@ -91,68 +148,178 @@ where
             return None;
         };
 
-        // Recover and validate frame counter
-        let maybe_frame_counter = {
-            // Recover the most-likely frame counter relative to the session state
-            let next_frame_counter = self.session.frame_counter(self.direction);
-            let frame_counter_lsbs = self.raw.frame_counter_lsbs();
-            Self::recover_frame_counter(frame_counter_lsbs, next_frame_counter)
+        // Reconstruct and validate the frame counter
+        let nwkskey = *self.session.nwkskey();
+
+        // Unmask the frame-counter LSBs and `FCtrl`, reproducing the sample from the still-encrypted payload
+        #[cfg(feature = "header-protection")]
+        {
+            let subkey = header_protection::derive_subkey::<Aes>(&nwkskey);
+            let sample = self.raw.header_protection_sample();
+            self.raw.apply_header_mask(header_protection::mask::<Aes>(&subkey, &sample));
+        }
+
+        let expected = self.session.frame_counter(self.direction);
+        let appskey = *self.session.appskey();
+        let frame_counter = match self.raw.is_siv() {
+            true => {
+                Self::recover_frame_counter_siv(expected, self.max_forward_gap, &nwkskey, &appskey, self.direction, &mut self.raw)?
+            }
+            false => Self::recover_frame_counter(expected, self.max_forward_gap, &nwkskey, self.direction, &self.raw)?,
         };
-        let frame_counter @ ..Self::RESERVED_FRAME_COUNTER = maybe_frame_counter else {
+        let frame_counter @ ..Self::RESERVED_FRAME_COUNTER = frame_counter else {
             // Reject `u32::MAX` as this means the session is exhausted
             return None;
         };
 
-        // Validate MIC
-        let nwkskey = self.session.nwkskey();
-        let mic_valid = MicBuilder::<Aes>::new(&nwkskey)
-            .set_direction(self.direction)
-            .set_address(self.raw.address())
-            .set_frame_counter(frame_counter)
-            .verify(self.raw.header(), self.raw.payload(), self.raw.mic());
-        let true = mic_valid else {
-            // Reject invalid MICs
-            return None;
-        };
-
-        // Decrypt payload
-        let appskey = self.session.appskey();
-        CipherstreamBuilder::<Aes>::new(&appskey)
-            .set_direction(self.direction)
-            .set_address(self.raw.address())
-            .set_frame_counter(frame_counter)
-            .apply(self.raw.payload_mut());
+        // Decrypt FOpts and the payload; a SIV-sealed frame already had both decrypted inside
+        // `recover_frame_counter_siv`, since it needs the plaintext there to validate the MIC
+        if !self.raw.is_siv() {
+            CipherstreamBuilder::<Aes>::new(&nwkskey)
+                .set_direction(self.direction)
+                .set_address(self.raw.address())
+                .set_frame_counter(frame_counter)
+                .set_generation(self.raw.generation())
+                .apply_fopts(self.raw.fopts_mut());
+            CipherstreamBuilder::<Aes>::new(&appskey)
+                .set_direction(self.direction)
+                .set_address(self.raw.address())
+                .set_frame_counter(frame_counter)
+                .set_generation(self.raw.generation())
+                .apply(self.raw.payload_mut());
+        }
 
-        // Commit next frame counter
+        // Commit the next frame counter
         let next_frame_counter = frame_counter.saturating_add(1);
         self.session.set_frame_counter(next_frame_counter, self.direction);
 
         // Init next step
+        let mut fopts = [0; RawFrame::MAX_FOPTS_SIZE];
+        let fopts_len = self.raw.fopts().len();
+        #[allow(clippy::indexing_slicing)]
+        fopts[..fopts_len].copy_from_slice(self.raw.fopts());
         let (plaintext, plaintext_len) = self.raw.into_payload();
-        Some(Plaintext { plaintext, plaintext_len })
+        Some(Plaintext { plaintext, plaintext_len, fopts, fopts_len })
     }
 
-    /// Recovers the full frame counter relative to the expected next frame counter
+    /// Reconstructs the full 32-bit frame counter relative to the counter `expected` next for this direction, using
+    /// the same truncated-number decoding scheme as QUIC, and validates the MIC against it
     ///
     /// # Security Considerations
-    /// The best-effort recovery logic compares the lossy implicit frame counter to the expected next counter to recover
-    /// the most-likely frame counter. If the resulting frame counter is not the correct one, MIC validation will fail.
-    /// Therefore, an attacker might trick the logic into recovering a wrong frame counter, but they do not gain much
-    /// there, as the message will be discarded.
+    /// The frame only transmits the 16 least-significant bits of the counter. We recombine them with the high bits of
+    /// `expected`, and only unwrap a single epoch forward if that brings the candidate closer to `expected`. This
+    /// builder does not tolerate any reordering: a recovered counter that is not at least `expected`, or that lies
+    /// more than `max_forward_gap` ahead of it, is rejected outright, so a forged frame can fast-forward the session
+    /// by at most `max_forward_gap`. The MIC is validated only after these checks, so a forged frame can never itself
+    /// move the session state.
+    fn recover_frame_counter(
+        expected: u32,
+        max_forward_gap: u32,
+        nwkskey: &[u8; 16],
+        direction: Direction,
+        raw: &RawFrame,
+    ) -> Option<u32> {
+        let recovered = Self::recover_frame_counter_candidate(expected, max_forward_gap, raw)?;
+
+        // Only trust the candidate once the MIC actually verifies against it
+        let mic_valid = MicBuilder::<Aes>::new(nwkskey)
+            .set_direction(direction)
+            .set_address(raw.address())
+            .set_frame_counter(recovered)
+            .set_generation(raw.generation())
+            .verify(raw.header(), raw.fopts(), raw.payload(), raw.mic());
+        let true = mic_valid else {
+            return None;
+        };
+
+        Some(recovered)
+    }
+
+    /// Reconstructs the full 32-bit frame counter and validates a SIV-sealed frame, decrypting `FOpts` and the
+    /// payload in the process
     ///
-    /// As a side-effect, this logic also protects against replay attacks, because the recovered frame counter is always
-    /// equal to or higher than the next valid frame counter. If an attacker injects an older frame, this logic will
-    /// erroneously recover a larger and thus non-matching frame counter, yielding a MIC validation error.
+    /// # Security Considerations
+    /// Unlike [`Self::recover_frame_counter`], the payload here is decrypted using
+    /// [`CipherstreamBuilderWithGeneration::apply_siv`](crate::crypto::stream::CipherstreamBuilderWithGeneration::apply_siv),
+    /// which derives its keystream from the on-wire MIC rather than the frame counter, so decryption does not depend
+    /// on the counter candidate being correct. The candidate is therefore only used to decrypt `FOpts` (whose
+    /// keystream is still counter-derived) and to recompute the MIC over the recovered plaintext; the frame is
+    /// rejected if that MIC does not match the one transmitted on the wire.
+    fn recover_frame_counter_siv(
+        expected: u32,
+        max_forward_gap: u32,
+        nwkskey: &[u8; 16],
+        appskey: &[u8; 16],
+        direction: Direction,
+        raw: &mut RawFrame,
+    ) -> Option<u32> {
+        let candidate = Self::recover_frame_counter_candidate(expected, max_forward_gap, raw)?;
+
+        // Decrypt FOpts with the candidate counter; its keystream is still counter-derived
+        CipherstreamBuilder::<Aes>::new(nwkskey)
+            .set_direction(direction)
+            .set_address(raw.address())
+            .set_frame_counter(candidate)
+            .set_generation(raw.generation())
+            .apply_fopts(raw.fopts_mut());
+
+        // Decrypt the payload using the SIV keystream derived from the on-wire MIC, not the candidate counter
+        let mic = *raw.mic();
+        CipherstreamBuilder::<Aes>::new(appskey)
+            .set_direction(direction)
+            .set_address(raw.address())
+            .set_frame_counter(candidate)
+            .set_generation(raw.generation())
+            .apply_siv(&mic, raw.payload_mut());
+
+        // Only trust the candidate (and the now-decrypted FOpts/payload) once the MIC recomputed over the plaintext
+        // actually matches the one transmitted on the wire
+        let mic_valid = MicBuilder::<Aes>::new(nwkskey)
+            .set_direction(direction)
+            .set_address(raw.address())
+            .set_frame_counter(candidate)
+            .set_generation(raw.generation())
+            .verify(raw.header(), raw.fopts(), raw.payload(), &mic);
+        let true = mic_valid else {
+            return None;
+        };
+
+        Some(candidate)
+    }
+
+    /// Recombines the transmitted frame-counter LSBs with `expected` into a full 32-bit candidate, without validating
+    /// the MIC against it
     ///
-    /// # Important
-    /// This logic may return the [`RESERVED_FRAME_COUNTER`], the caller must check for this.
-    #[inline]
-    fn recover_frame_counter(frame_counter_lsbs: u16, next_frame_counter: u32) -> u32 {
-        // Recover the frame counter
-        match (next_frame_counter & 0xFFFF_0000) | (frame_counter_lsbs as u32) {
-            recovered if recovered >= next_frame_counter => recovered,
-            recovered => recovered.saturating_add(0x1_0000),
+    /// # Implementation Note
+    /// This is split out from [`Self::recover_frame_counter`] so [`Self::recover_frame_counter_siv`] can obtain a
+    /// candidate counter to decrypt `FOpts` with before the MIC (which, for a SIV-sealed frame, can only be validated
+    /// after decryption) is available to gate it.
+    fn recover_frame_counter_candidate(expected: u32, max_forward_gap: u32, raw: &RawFrame) -> Option<u32> {
+        // The transmitted counter bits and the derived window constants
+        const BITS: u32 = 16;
+        const WIN: u32 = 1 << BITS;
+        const HWIN: u32 = WIN / 2;
+        const MASK: u32 = WIN - 1;
+
+        // Recombine the high bits of `expected` with the transmitted low bits, then unwrap one epoch forward if the
+        // recombined candidate would otherwise lie half a window or more behind `expected`
+        let lsbs = u32::from(raw.frame_counter_lsbs());
+        let candidate = (expected & !MASK) | lsbs;
+        let recovered = match candidate.checked_add(HWIN) {
+            Some(sum) if sum <= expected => candidate.checked_add(WIN).filter(|&wrapped| wrapped <= Self::RESERVED_FRAME_COUNTER),
+            _ => None,
         }
+        .unwrap_or(candidate);
+
+        // This builder never tolerates reordering, and bounds how far ahead of `expected` a frame may jump
+        let true = recovered >= expected else {
+            return None;
+        };
+        let true = recovered.saturating_sub(expected) <= max_forward_gap else {
+            return None;
+        };
+
+        Some(recovered)
     }
 }
 
@@ -163,6 +330,17 @@ pub struct Plaintext {
     plaintext: [u8; MAX_PAYLOAD_SIZE],
     /// The amount of bytes within the payload buffer
     plaintext_len: usize,
+    /// The decrypted piggybacked control channel buffer
+    fopts: [u8; RawFrame::MAX_FOPTS_SIZE],
+    /// The amount of bytes within the `fopts` buffer
+    fopts_len: usize,
+}
+impl Plaintext {
+    /// Returns the decrypted piggybacked control channel carried alongside this payload, if any
+    pub fn fopts(&self) -> &[u8] {
+        #[allow(clippy::indexing_slicing)]
+        &self.fopts[..self.fopts_len]
+    }
 }
 impl Deref for Plaintext {
     type Target = [u8];
@@ -1,17 +1,78 @@
 //! A raw frame structure for (de-)serialisation
 
-use crate::frame::{MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE};
+use crate::{
+    frame::{MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE},
+    Direction,
+};
+
+/// A cipher suite identifier, encoded in the frame's `FPort` byte
+///
+/// # Implementation Note
+/// [`RawFrame::parse`] no longer hard-rejects a frame whose `FPort` byte does not match the suite this crate
+/// implements; it merely surfaces the suite via [`RawFrame::cipher_suite`] and leaves it up to the caller (e.g.
+/// [`crate::frame::plaintext::PlaintextBuilder`]/[`crate::frame::control::ControlFrameOpener`]) to reject suites it
+/// cannot handle. This keeps old frames decodable while leaving room to add e.g. an AES-CBC-based suite under a new
+/// value later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherSuite {
+    /// AES-CTR for confidentiality and AES-CMAC (truncated to [`RawFrame::MIC_SIZE`] bytes) for integrity; the suite
+    /// implemented by this crate today
+    AesCtrAesCmac,
+    /// A frame carrying TLV-encoded control/MAC commands, encrypted with the network session key instead of the
+    /// application session key; see [`crate::frame::control`]
+    Control,
+    /// A cipher suite not (yet) known to this crate
+    Unknown(u8),
+}
+impl CipherSuite {
+    /// Recovers the cipher suite from its wire byte
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            RawFrame::FPORT => Self::AesCtrAesCmac,
+            RawFrame::CONTROL_FPORT => Self::Control,
+            other => Self::Unknown(other),
+        }
+    }
+    /// Encodes the cipher suite as its wire byte
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::AesCtrAesCmac => RawFrame::FPORT,
+            Self::Control => RawFrame::CONTROL_FPORT,
+            Self::Unknown(other) => other,
+        }
+    }
+}
 
 /// A raw frame structure for (de-)serialisation
 ///
 /// # Implementation Note
-/// This frame uses the proprietary frame type (`FType: 0b111`), and strips the unused `FCtrl`, `FOpts` and `FPort`
-/// fields. Otherwise, the basic frame structure is identical to normal uplink/downlink frames; especially with regards
-/// to context information like device address and frame counter.
+/// This frame uses the proprietary frame type (`FType: 0b111`), and places an optional, variable-length `FOpts`
+/// region (see [`Self::fopts`]/[`Self::set_fopts`]) between the fixed header and the payload, but appends a trailing
+/// key generation byte after `FPort` so a receiver can follow a session key ratchet. Otherwise, the basic frame
+/// structure is identical to normal uplink/downlink frames; especially with regards to context information like
+/// device address, `FCtrl` and frame counter.
+///
+/// # Implementation Note
+/// The upper nibble of `FCtrl` is reserved (always `0`), except for the [`Self::is_siv`] marker bit, unless the
+/// `header-protection` feature is enabled, in which case it (together with the frame-counter LSBs) is masked on the
+/// wire; see [`Self::header_protection_sample`]/[`Self::apply_header_mask`]. The byte is always present in the header
+/// regardless of the feature, so the header layout does not change depending on which side of a link has it enabled.
+/// The low nibble always holds the [`Self::fopts`] length and is never masked, since [`Self::parse`] needs it in the
+/// clear to locate the payload.
+///
+/// # Implementation Note
+/// [`Self::parse_lorawan`]/[`Self::into_lorawan_frame`] are a parallel codec for the standard LoRaWAN data MTypes
+/// (`FType: 0b010`/`0b011`/`0b100`/`0b101`), so a frame produced by (or destined for) a real LoRaWAN stack can be
+/// decoded into the same in-memory representation and handed off to the rest of this crate.
 #[derive(Debug, Clone, Copy)]
 pub struct RawFrame {
     /// The frame header
     header: [u8; Self::HEADER_SIZE],
+    /// The piggybacked control/MAC-command bytes, carried (and encrypted with their own keystream) between the
+    /// header and the payload; see [`Self::fopts`]/[`Self::set_fopts`]
+    fopts: [u8; Self::MAX_FOPTS_SIZE],
+    /// The amount of bytes within the `FOpts` buffer
+    fopts_len: usize,
     /// The payload buffer
     payload: [u8; MAX_PAYLOAD_SIZE],
     /// The amount of bytes within the payload buffer
@@ -25,10 +86,30 @@ impl RawFrame {
     const MHDR: u8 = 0b111_000_00;
     /// The FPort which we (ab)use as version indicator
     const FPORT: u8 = 0x01;
+    /// The FPort value used to mark a frame as carrying control/MAC commands instead of application payload
+    const CONTROL_FPORT: u8 = 0x00;
     /// The header length in bytes
-    const HEADER_SIZE: usize = 8;
+    pub const HEADER_SIZE: usize = 10;
     /// The MIC length in bytes
-    const MIC_SIZE: usize = 8;
+    pub const MIC_SIZE: usize = 8;
+
+    /// The `MHDR` mask selecting the `MType` bits
+    const LORAWAN_MTYPE_MASK: u8 = 0b111_00000;
+    /// The `MHDR` byte of a standard unconfirmed-data-uplink frame
+    const LORAWAN_UNCONFIRMED_UP: u8 = 0b010_00000;
+    /// The `MHDR` byte of a standard unconfirmed-data-downlink frame
+    const LORAWAN_UNCONFIRMED_DOWN: u8 = 0b011_00000;
+    /// The `MHDR` byte of a standard confirmed-data-uplink frame
+    const LORAWAN_CONFIRMED_UP: u8 = 0b100_00000;
+    /// The `MHDR` byte of a standard confirmed-data-downlink frame
+    const LORAWAN_CONFIRMED_DOWN: u8 = 0b101_00000;
+    /// The canonical MIC length (in bytes) used by the standard LoRaWAN wire format, regardless of this crate's own
+    /// [`Self::MIC_SIZE`]/`extended-mic` conventions
+    const LORAWAN_MIC_SIZE: usize = 4;
+
+    /// The maximum amount of piggybacked `FOpts` bytes, bounded by the 4 bits available to encode its length in the
+    /// low nibble of `FCtrl`
+    pub const MAX_FOPTS_SIZE: usize = 15;
 
     /// Create a new unitialized frame with only the fixed constants and the given payload set
     ///
@@ -43,33 +124,54 @@ impl RawFrame {
 
         // Return the new frame
         RawFrame {
-            header: [Self::MHDR, 0, 0, 0, 0, 0, 0, Self::FPORT],
+            header: [Self::MHDR, 0, 0, 0, 0, 0, 0, 0, Self::FPORT, 0],
+            fopts: [0; Self::MAX_FOPTS_SIZE],
+            fopts_len: 0,
             payload: payload_,
             payload_len: payload.len(),
             mic: [0; Self::MIC_SIZE],
         }
     }
+    /// Peeks the device address out of a not yet fully parsed frame
+    ///
+    /// # Implementation Note
+    /// This only looks at the address bytes in the header and performs no other validation, so it can be used to
+    /// route a frame to the right session before paying for the full [`RawFrame::parse`].
+    pub fn peek_address(frame: &[u8]) -> Option<u32> {
+        let [_, addr0, addr1, addr2, addr3] = *frame.first_chunk::<5>()?;
+        Some(u32::from_le_bytes([addr0, addr1, addr2, addr3]))
+    }
     /// Parses the frame
+    ///
+    /// # Implementation Note
+    /// The amount of piggybacked `FOpts` bytes between the header and the payload is given by the low nibble of
+    /// `FCtrl`, which this crate never masks (see [`Self::apply_header_mask`]), so it can always be read here
+    /// regardless of whether the `header-protection` feature is in use on either side of the link.
     pub fn parse(frame: &[u8]) -> Option<Self> {
-        // Split frame
-        let payload_len = frame.len().checked_sub(Self::HEADER_SIZE)?.checked_sub(Self::MIC_SIZE)?;
+        // Split off the header first, since the `FOpts` length is encoded in it
         let (header, data) = frame.split_at_checked(Self::HEADER_SIZE)?;
-        let (payload, mic) = data.split_at_checked(payload_len)?;
-
-        // Get header and MIC as arrays and check header
         let header = header.first_chunk()?;
-        let mic = mic.first_chunk()?;
-        let _valid_header @ [Self::MHDR, _, _, _, _, _, _, Self::FPORT] = header else {
+        let _valid_header @ [Self::MHDR, _, _, _, _, _, _, _, _, _] = header else {
             // The header is unexpected
             return None;
         };
 
-        // Copy the payload
+        // Split `FOpts`, payload and MIC
+        let [_, _, _, _, _, fctrl, _, _, _, _] = *header;
+        let fopts_len = usize::from(fctrl & 0x0F);
+        let (fopts, data) = data.split_at_checked(fopts_len)?;
+        let payload_len = data.len().checked_sub(Self::MIC_SIZE)?;
+        let (payload, mic) = data.split_at_checked(payload_len)?;
+        let mic = mic.first_chunk()?;
+
+        // Copy `FOpts` and payload
+        let mut fopts_ = [0; Self::MAX_FOPTS_SIZE];
+        fopts_.get_mut(..fopts_len)?.copy_from_slice(fopts);
         let mut payload_ = [0; MAX_PAYLOAD_SIZE];
         payload_.get_mut(..payload_len)?.copy_from_slice(payload);
 
         // Return the parsed frame
-        Some(Self { header: *header, payload: payload_, payload_len, mic: *mic })
+        Some(Self { header: *header, fopts: fopts_, fopts_len, payload: payload_, payload_len, mic: *mic })
     }
     /// Serializes the frame and returns a tuple with the buffer and the amount of bytes in there (aka serialized frame
     /// length)
@@ -81,16 +183,109 @@ impl RawFrame {
         // Note: The buffer should always be able to hold the entire frame
         #[allow(clippy::indexing_slicing)]
         {
-            // Write header, payload and MIC to the buffer
+            // Write header, `FOpts`, payload and MIC to the buffer
             buffer[..Self::HEADER_SIZE].copy_from_slice(&self.header);
-            buffer[Self::HEADER_SIZE..][..self.payload_len].copy_from_slice(&self.payload[..self.payload_len]);
-            buffer[Self::HEADER_SIZE..][self.payload_len..][..Self::MIC_SIZE].copy_from_slice(&self.mic);
+            buffer[Self::HEADER_SIZE..][..self.fopts_len].copy_from_slice(&self.fopts[..self.fopts_len]);
+            buffer[Self::HEADER_SIZE..][self.fopts_len..][..self.payload_len].copy_from_slice(&self.payload[..self.payload_len]);
+            buffer[Self::HEADER_SIZE..][self.fopts_len..][self.payload_len..][..Self::MIC_SIZE].copy_from_slice(&self.mic);
         }
 
         // Return tuple
         // Note; This should always be smaller than `usize::MAX`
         #[allow(clippy::arithmetic_side_effects)]
-        let frame_length = Self::HEADER_SIZE + self.payload_len + Self::MIC_SIZE;
+        let frame_length = Self::HEADER_SIZE + self.fopts_len + self.payload_len + Self::MIC_SIZE;
+        (buffer, frame_length)
+    }
+
+    /// Parses a standard LoRaWAN 1.0 unconfirmed/confirmed data frame, inferring the [`Direction`] from the `MType`
+    ///
+    /// # Implementation Note
+    /// This is the counterpart to [`Self::into_lorawan_frame`], letting loreyawen sit next to a real LoRaWAN stack
+    /// (e.g. a gateway decoding frames produced by `lorawan-encoding`): it reads the variable-length `FOpts` (0..15
+    /// bytes, its length given by the low nibble of `FCtrl`) and skips it between `FCnt` and `FPort`, and accepts the
+    /// canonical 4-byte MIC instead of this crate's own [`Self::MIC_SIZE`]. The generation byte has no standard
+    /// LoRaWAN counterpart, so a frame parsed this way is always reported as generation `0`; a caller that ratchets
+    /// keys over a LoRaWAN-interop link has to track the generation out of band.
+    pub fn parse_lorawan(frame: &[u8]) -> Option<(Self, Direction)> {
+        // Read MHDR and infer the direction from the MType; reject anything but unconfirmed/confirmed data
+        let (&mhdr, rest) = frame.split_first()?;
+        let direction = match mhdr & Self::LORAWAN_MTYPE_MASK {
+            Self::LORAWAN_UNCONFIRMED_UP | Self::LORAWAN_CONFIRMED_UP => Direction::Uplink,
+            Self::LORAWAN_UNCONFIRMED_DOWN | Self::LORAWAN_CONFIRMED_DOWN => Direction::Downlink,
+            _ => return None,
+        };
+
+        // Read address, `FCtrl` and `FCnt`, then skip the variable-length `FOpts` it announces
+        //
+        // Note: The incoming `FOpts` bytes are discarded rather than kept as this crate's own piggybacked `FOpts`
+        // (see `Self::fopts`/`Self::set_fopts`): a real LoRaWAN network server encrypts them with the network
+        // session key using LoRaWAN's own per-FOpts counter, which this crate does not replicate.
+        let (address, rest) = rest.split_at_checked(4)?;
+        let &[addr0, addr1, addr2, addr3] = address else { return None };
+        let (&fctrl, rest) = rest.split_first()?;
+        let (fcnt, rest) = rest.split_at_checked(2)?;
+        let &[fcnt0, fcnt1] = fcnt else { return None };
+        let fopts_len = usize::from(fctrl & 0x0F);
+        let (_fopts, rest) = rest.split_at_checked(fopts_len)?;
+
+        // `FPort` and `FRMPayload` are both absent if `rest` holds nothing but the trailing MIC; otherwise `FPort` is
+        // the first remaining byte and everything between it and the MIC is the payload
+        let (fport, rest) = match rest.len() {
+            Self::LORAWAN_MIC_SIZE => (0, rest),
+            _ => rest.split_first().map(|(&fport, rest)| (fport, rest))?,
+        };
+        let payload_len = rest.len().checked_sub(Self::LORAWAN_MIC_SIZE)?;
+        let (payload, mic) = rest.split_at_checked(payload_len)?;
+
+        // Copy the payload
+        let mut payload_ = [0; MAX_PAYLOAD_SIZE];
+        payload_.get_mut(..payload_len)?.copy_from_slice(payload);
+
+        // Copy the truncated MIC, zero-extending it to this crate's own (wider) internal MIC buffer
+        let mic: &[u8; Self::LORAWAN_MIC_SIZE] = mic.first_chunk()?;
+        let mut mic_ = [0; Self::MIC_SIZE];
+        mic_.get_mut(..Self::LORAWAN_MIC_SIZE)?.copy_from_slice(mic);
+
+        // Assemble the header; the generation byte is always `0`, as standard LoRaWAN has no such concept. The `FOpts`
+        // length nibble is forced to `0` to match the (empty) `fopts` buffer, since the original `FOpts` were dropped
+        let header = [Self::MHDR, addr0, addr1, addr2, addr3, fctrl & 0xF0, fcnt0, fcnt1, fport, 0];
+        let fopts = [0; Self::MAX_FOPTS_SIZE];
+        Some((Self { header, fopts, fopts_len: 0, payload: payload_, payload_len, mic: mic_ }, direction))
+    }
+
+    /// Serializes the frame as a standard LoRaWAN 1.0 unconfirmed data frame for the given direction, carrying over
+    /// `FOpts` (with the low nibble of `FCtrl` set to its length) and the canonical 4-byte MIC, and returns a tuple
+    /// with the buffer and the amount of bytes in there (aka serialized frame length)
+    ///
+    /// # Implementation Note
+    /// This is the counterpart to [`Self::parse_lorawan`]. The key generation is not carried over, as standard
+    /// LoRaWAN framing has no concept of it; a caller relying on ratcheting over a LoRaWAN-interop link has to track
+    /// the generation out of band instead of reading it off the wire.
+    pub fn into_lorawan_frame(self, direction: Direction) -> ([u8; MAX_MESSAGE_SIZE], usize) {
+        let mhdr = match direction {
+            Direction::Uplink => Self::LORAWAN_UNCONFIRMED_UP,
+            Direction::Downlink => Self::LORAWAN_UNCONFIRMED_DOWN,
+        };
+        let [_, addr0, addr1, addr2, addr3, _, fcnt0, fcnt1, fport, _] = self.header;
+        #[allow(clippy::cast_possible_truncation, reason = "fopts_len never exceeds MAX_FOPTS_SIZE (15)")]
+        let fctrl = self.fopts_len as u8;
+
+        let mut buffer = [0; MAX_MESSAGE_SIZE];
+        #[allow(clippy::indexing_slicing)]
+        {
+            buffer[0] = mhdr;
+            buffer[1..5].copy_from_slice(&[addr0, addr1, addr2, addr3]);
+            buffer[5] = fctrl;
+            buffer[6..8].copy_from_slice(&[fcnt0, fcnt1]);
+            buffer[8..][..self.fopts_len].copy_from_slice(&self.fopts[..self.fopts_len]);
+            buffer[8..][self.fopts_len] = fport;
+            buffer[9..][self.fopts_len..][..self.payload_len].copy_from_slice(&self.payload[..self.payload_len]);
+            buffer[9..][self.fopts_len..][self.payload_len..][..Self::LORAWAN_MIC_SIZE]
+                .copy_from_slice(&self.mic[..Self::LORAWAN_MIC_SIZE]);
+        }
+
+        #[allow(clippy::arithmetic_side_effects)]
+        let frame_length = 9 + self.fopts_len + self.payload_len + Self::LORAWAN_MIC_SIZE;
         (buffer, frame_length)
     }
 
@@ -101,26 +296,164 @@ impl RawFrame {
 
     /// The address of the end device associated with the frame
     pub fn address(&self) -> u32 {
-        let [_, addr0, addr1, addr2, addr3, _, _, _] = self.header;
+        let [_, addr0, addr1, addr2, addr3, _, _, _, _, _] = self.header;
         u32::from_le_bytes([addr0, addr1, addr2, addr3])
     }
     /// The address of the end device associated with the frame
     pub fn set_address(&mut self, address: u32) {
-        let [mhdr, _, _, _, _, fcnt0, fcnt1, fport] = self.header;
+        let [mhdr, _, _, _, _, fctrl, fcnt0, fcnt1, fport, generation] = self.header;
         let [addr0, addr1, addr2, addr3] = address.to_le_bytes();
-        self.header = [mhdr, addr0, addr1, addr2, addr3, fcnt0, fcnt1, fport];
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
     }
 
     /// The least significant bytes of the frame counter
     pub fn frame_counter_lsbs(&self) -> u16 {
-        let [_, _, _, _, _, fcnt0, fcnt1, _] = self.header;
+        let [_, _, _, _, _, _, fcnt0, fcnt1, _, _] = self.header;
         u16::from_le_bytes([fcnt0, fcnt1])
     }
     /// Sets the least significant bytes of the frame counter
     pub fn set_frame_counter_lsbs(&mut self, frame_counter_lsbs: u16) {
-        let [mhdr, addr0, addr1, addr2, addr3, _, _, fport] = self.header;
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, _, _, fport, generation] = self.header;
         let [fcnt0, fcnt1] = frame_counter_lsbs.to_le_bytes();
-        self.header = [mhdr, addr0, addr1, addr2, addr3, fcnt0, fcnt1, fport];
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
+    }
+
+    /// The `FCtrl` byte
+    ///
+    /// # Implementation Note
+    /// The low nibble always holds the amount of bytes in [`Self::fopts`]; see [`Self::set_fopts`]. The upper nibble
+    /// carries the [`Self::is_siv`] marker bit and is otherwise reserved (always `0`); it is masked on the wire if
+    /// the `header-protection` feature is enabled.
+    pub fn fctrl(&self) -> u8 {
+        let [_, _, _, _, _, fctrl, _, _, _, _] = self.header;
+        fctrl
+    }
+    /// Sets the `FCtrl` byte
+    ///
+    /// # Implementation Note
+    /// This does not touch the low nibble ([`Self::fopts`]'s length); only the upper, otherwise-reserved bits are
+    /// overwritten.
+    pub fn set_fctrl(&mut self, fctrl: u8) {
+        let [mhdr, addr0, addr1, addr2, addr3, old_fctrl, fcnt0, fcnt1, fport, generation] = self.header;
+        let fctrl = (fctrl & 0xF0) | (old_fctrl & 0x0F);
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
+    }
+
+    /// The `FCtrl` bit that marks a frame as sealed with the nonce-misuse-resistant SIV-style payload keystream
+    /// instead of the regular counter-derived one; see [`Self::is_siv`]/[`Self::set_siv`].
+    const SIV_FCTRL_BIT: u8 = 0b0001_0000;
+
+    /// Whether this frame's payload was sealed with the SIV-style keystream (see [`Self::set_siv`]) rather than the
+    /// regular counter-derived one
+    pub fn is_siv(&self) -> bool {
+        self.fctrl() & Self::SIV_FCTRL_BIT != 0
+    }
+    /// Marks (or unmarks) this frame as sealed with the SIV-style keystream
+    pub fn set_siv(&mut self, siv: bool) {
+        let fctrl = match siv {
+            true => self.fctrl() | Self::SIV_FCTRL_BIT,
+            false => self.fctrl() & !Self::SIV_FCTRL_BIT,
+        };
+        self.set_fctrl(fctrl);
+    }
+
+    /// The cipher suite the frame was sealed with
+    pub fn cipher_suite(&self) -> CipherSuite {
+        let [_, _, _, _, _, _, _, _, fport, _] = self.header;
+        CipherSuite::from_byte(fport)
+    }
+    /// Sets the cipher suite the frame is sealed with
+    pub fn set_cipher_suite(&mut self, cipher_suite: CipherSuite) {
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, _, generation] = self.header;
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, cipher_suite.to_byte(), generation];
+    }
+
+    /// The key generation ("ratchet epoch") that the frame was sealed under
+    pub fn generation(&self) -> u8 {
+        let [_, _, _, _, _, _, _, _, _, generation] = self.header;
+        generation
+    }
+    /// Sets the key generation ("ratchet epoch") that the frame is sealed under
+    pub fn set_generation(&mut self, generation: u8) {
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, _] = self.header;
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
+    }
+
+    /// Returns the 16-byte ciphertext sample used to derive the header-protection mask: a fixed-offset window into
+    /// the (already-encrypted) `FOpts`-then-payload region, zero-padded if that region is shorter than the sample
+    ///
+    /// # Implementation Note
+    /// Available only with the `header-protection` feature enabled; see [`crate::crypto::header_protection`]. `FOpts`
+    /// and the payload are encrypted under distinct keystreams (see [`crate::crypto::stream`]), but are adjacent on
+    /// the wire, so they are treated as one contiguous ciphertext region for sampling purposes.
+    #[cfg(feature = "header-protection")]
+    pub fn header_protection_sample(&self) -> [u8; 16] {
+        /// The byte offset into the `FOpts`-then-payload region at which the sample is taken
+        const SAMPLE_OFFSET: usize = 4;
+
+        let mut sample = [0; 16];
+        let body_len = self.fopts_len.saturating_add(self.payload_len);
+        let available = body_len.saturating_sub(SAMPLE_OFFSET).min(sample.len());
+        #[allow(clippy::indexing_slicing)]
+        for (i, byte) in sample.iter_mut().enumerate().take(available) {
+            let offset = SAMPLE_OFFSET + i;
+            *byte = match offset.checked_sub(self.fopts_len) {
+                Some(payload_offset) => self.payload[payload_offset],
+                None => self.fopts[offset],
+            };
+        }
+        sample
+    }
+    /// XORs the given mask into the frame-counter LSBs and the upper, otherwise-reserved nibble of `FCtrl`
+    ///
+    /// # Implementation Note
+    /// Masking is a straight XOR, so calling this a second time with the same mask unmasks the header again; see
+    /// [`crate::crypto::header_protection`]. The low nibble of `FCtrl` (the [`Self::fopts`] length) is never masked,
+    /// as [`Self::parse`] has to read it in the clear to know where `FOpts` ends and the payload begins.
+    #[cfg(feature = "header-protection")]
+    pub fn apply_header_mask(&mut self, mask: [u8; 3]) {
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation] = self.header;
+        self.header = [
+            mhdr,
+            addr0,
+            addr1,
+            addr2,
+            addr3,
+            fctrl ^ (mask[0] & 0xF0),
+            fcnt0 ^ mask[1],
+            fcnt1 ^ mask[2],
+            fport,
+            generation,
+        ];
+    }
+
+    /// The piggybacked control/MAC-command bytes carried in `FOpts`, alongside (but encrypted separately from) the
+    /// payload
+    pub fn fopts(&self) -> &[u8] {
+        // Note: The FOpts length is assumed to be valid here
+        #[allow(clippy::indexing_slicing)]
+        &self.fopts[..self.fopts_len]
+    }
+    /// The piggybacked control/MAC-command bytes carried in `FOpts`
+    pub fn fopts_mut(&mut self) -> &mut [u8] {
+        // Note: The FOpts length is assumed to be valid here
+        #[allow(clippy::indexing_slicing)]
+        &mut self.fopts[..self.fopts_len]
+    }
+    /// Sets the piggybacked control/MAC-command bytes to carry in `FOpts`, updating the low nibble of `FCtrl` to the
+    /// new length
+    ///
+    /// # Panics
+    /// This function panics if `fopts` is longer than [`Self::MAX_FOPTS_SIZE`].
+    pub fn set_fopts(&mut self, fopts: &[u8]) {
+        self.fopts.get_mut(..fopts.len()).expect("fopts is too large").copy_from_slice(fopts);
+        self.fopts_len = fopts.len();
+
+        #[allow(clippy::cast_possible_truncation, reason = "fopts.len() was just checked against MAX_FOPTS_SIZE (15)")]
+        let fopts_len = self.fopts_len as u8;
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation] = self.header;
+        let fctrl = (fctrl & 0xF0) | fopts_len;
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
     }
 
     /// The payload bytes
@@ -13,15 +13,16 @@ use crate::frame::{MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE};
 /// `loreyawen` uses a LoRaWAN-proprietary frame format, with the following fields:
 /// - 1 byte `MHDR`, fixed to `0b111_000_00` (indicates a "proprietary" frame for LoRaWAN version 1.0)
 /// - 8 bytes `FHDR`, consisting of 4 bytes `DevAddr`, 1 byte `FCtrl`, 2 bytes `FCnt`, and 1 byte `FPort`
+/// - 1 byte key generation ("ratchet epoch"), appended after `FPort`; see [`Self::generation`]/[`Self::set_generation`]
 /// - N bytes encrypted payload
 /// - 4 or 8 bytes `MIC` (which is just a less-truncated version of the default LoRaWAN MIC)
 ///
 /// ```ascii
 /// Loreyawen Frame:
-/// MHDR[1] | DevAddr[4] | FCtrl[1] | FCnt[2] |     FOpts[0] |    FPort[1] | Payload[N] | MIC[4 or 8]
+/// MHDR[1] | DevAddr[4] | FCtrl[1] | FCnt[2] |     FOpts[0] |    FPort[1] | Generation[1] | Payload[N] | MIC[4 or 8]
 ///
 /// LoRaWAN Uplink/Downlink Frame as Reference:
-/// MHDR[1] | DevAddr[4] | FCtrl[1] | FCnt[2] | FOpts[0..15] | FPort[0..1] | Payload[N] | MIC[4]
+/// MHDR[1] | DevAddr[4] | FCtrl[1] | FCnt[2] | FOpts[0..15] | FPort[0..1] |                 Payload[N] | MIC[4]
 /// ```
 #[derive(Debug, Clone, Copy)]
 pub struct RawFrame {
@@ -39,7 +40,7 @@ impl RawFrame {
     #[allow(clippy::unusual_byte_groupings, reason = "Uses the message header grouping")]
     const MHDR: u8 = 0b111_000_00;
     /// The header length in bytes
-    pub const HEADER_SIZE: usize = 9;
+    pub const HEADER_SIZE: usize = 10;
     /// The MIC length in bytes
     pub const MIC_SIZE: usize = match cfg!(feature = "extended-mic") {
         true => 8,
@@ -59,7 +60,7 @@ impl RawFrame {
 
         // Return the new frame
         RawFrame {
-            header: [Self::MHDR, 0, 0, 0, 0, 0, 0, 0, 0],
+            header: [Self::MHDR, 0, 0, 0, 0, 0, 0, 0, 0, 0],
             payload: payload_,
             payload_len: payload.len(),
             mic: [0; Self::MIC_SIZE],
@@ -75,7 +76,7 @@ impl RawFrame {
         // Get header and MIC as arrays and check header
         let header = header.first_chunk()?;
         let mic = mic.first_chunk()?;
-        let _valid_header @ [Self::MHDR, _, _, _, _, _, _, _, _] = header else {
+        let _valid_header @ [Self::MHDR, _, _, _, _, _, _, _, _, _] = header else {
             // The header is unexpected
             return None;
         };
@@ -117,48 +118,59 @@ impl RawFrame {
 
     /// The address of the end device associated with the frame
     pub fn address(&self) -> u32 {
-        let [_, addr0, addr1, addr2, addr3, _, _, _, _] = self.header;
+        let [_, addr0, addr1, addr2, addr3, _, _, _, _, _] = self.header;
         u32::from_le_bytes([addr0, addr1, addr2, addr3])
     }
     /// The address of the end device associated with the frame
     pub fn set_address(&mut self, address: u32) {
-        let [mhdr, _, _, _, _, fctrl, fcnt0, fcnt1, fport] = self.header;
+        let [mhdr, _, _, _, _, fctrl, fcnt0, fcnt1, fport, generation] = self.header;
         let [addr0, addr1, addr2, addr3] = address.to_le_bytes();
-        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport];
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
     }
 
     /// The least significant bytes of the frame counter
     pub fn frame_counter_lsbs(&self) -> u16 {
-        let [_, _, _, _, _, _, fcnt0, fcnt1, _] = self.header;
+        let [_, _, _, _, _, _, fcnt0, fcnt1, _, _] = self.header;
         u16::from_le_bytes([fcnt0, fcnt1])
     }
     /// Sets the least significant bytes of the frame counter
     pub fn set_frame_counter_lsbs(&mut self, frame_counter_lsbs: u16) {
-        let [mhdr, addr0, addr1, addr2, addr3, fctrl, _, _, fport] = self.header;
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, _, _, fport, generation] = self.header;
         let [fcnt0, fcnt1] = frame_counter_lsbs.to_le_bytes();
-        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport];
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
     }
 
     /// Gets the `FCtrl` byte
     pub fn frame_ctrl(&self) -> u8 {
-        let [_, _, _, _, _, fctrl, _, _, _] = self.header;
+        let [_, _, _, _, _, fctrl, _, _, _, _] = self.header;
         fctrl
     }
     /// Sets the `FCtrl` byte
     pub fn set_frame_ctrl(&mut self, frame_ctrl: u8) {
-        let [mhdr, addr0, addr1, addr2, addr3, _, fcnt0, fcnt1, fport] = self.header;
-        self.header = [mhdr, addr0, addr1, addr2, addr3, frame_ctrl, fcnt0, fcnt1, fport];
+        let [mhdr, addr0, addr1, addr2, addr3, _, fcnt0, fcnt1, fport, generation] = self.header;
+        self.header = [mhdr, addr0, addr1, addr2, addr3, frame_ctrl, fcnt0, fcnt1, fport, generation];
     }
 
     /// Gets the `FPort` byte
     pub fn frame_port(&self) -> u8 {
-        let [_, _, _, _, _, _, _, _, fport] = self.header;
+        let [_, _, _, _, _, _, _, _, fport, _] = self.header;
         fport
     }
     /// Sets the `FCtrl` byte
     pub fn set_frame_port(&mut self, frame_port: u8) {
-        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, _] = self.header;
-        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, frame_port];
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, _, generation] = self.header;
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, frame_port, generation];
+    }
+
+    /// The key generation ("ratchet epoch") that the frame was sealed under
+    pub fn generation(&self) -> u8 {
+        let [_, _, _, _, _, _, _, _, _, generation] = self.header;
+        generation
+    }
+    /// Sets the key generation ("ratchet epoch") that the frame is sealed under
+    pub fn set_generation(&mut self, generation: u8) {
+        let [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, _] = self.header;
+        self.header = [mhdr, addr0, addr1, addr2, addr3, fctrl, fcnt0, fcnt1, fport, generation];
     }
 
     /// The payload bytes
@@ -1,61 +1,96 @@
 //! A builder to encrypt and seal a plaintext into a sealed frame
 
+#[cfg(feature = "header-protection")]
+use crate::crypto::header_protection;
 use crate::{
-    crypto::{mic::MicBuilder, stream::CipherstreamBuilder, Aes128},
+    crypto::{
+        mic::MicBuilder,
+        ratchet::{self, APPSKEY_LABEL, NWKSKEY_LABEL},
+        stream::CipherstreamBuilder,
+        Aes128,
+    },
     frame::{raw::RawFrame, MAX_MESSAGE_SIZE},
     Direction, SessionState,
 };
 use core::{marker::PhantomData, ops::Deref};
 
+/// The default frame counter value at which the session is ratcheted to the next key generation, rather than letting
+/// the counter run to exhaustion
+const DEFAULT_RATCHET_THRESHOLD: u32 = u32::MAX - 0xFFFF;
+
 /// A builder to encrypt and seal a plaintext into a sealed frame
+///
+/// # Implementation Note
+/// `RATCHET_THRESHOLD` is the frame counter value at which [`Self::set_payload`] ratchets the session to the next key
+/// generation instead of letting the counter run towards exhaustion; it defaults to [`DEFAULT_RATCHET_THRESHOLD`], but
+/// a caller that wants to rekey earlier (or never, by passing `u32::MAX`) can override it explicitly, e.g.
+/// `FrameBuilder::<_, _, 1_000>::new(session)`.
 #[derive(Debug)]
-pub struct FrameBuilder<Session, Aes> {
+pub struct FrameBuilder<Session, Aes, const RATCHET_THRESHOLD: u32 = DEFAULT_RATCHET_THRESHOLD> {
     /// The underlying session state
     session: Session,
     /// The underlying implementation
     _aes: PhantomData<Aes>,
 }
-impl<Session, Aes> FrameBuilder<Session, Aes> {
+impl<Session, Aes, const RATCHET_THRESHOLD: u32> FrameBuilder<Session, Aes, RATCHET_THRESHOLD> {
     /// Create a new builder for the given session and implementation
     pub fn new(session: Session) -> Self {
         Self { session, _aes: PhantomData }
     }
 
     /// Set the direction of the associated message
-    pub fn set_direction(self, direction: Direction) -> FrameBuilderWithDirection<Session, Aes> {
-        FrameBuilderWithDirection { session: self.session, direction, _aes: self._aes }
+    pub fn set_direction(self, direction: Direction) -> FrameBuilderWithDirection<Session, Aes, RATCHET_THRESHOLD> {
+        FrameBuilderWithDirection {
+            session: self.session,
+            direction,
+            fopts: [0; RawFrame::MAX_FOPTS_SIZE],
+            fopts_len: 0,
+            _aes: self._aes,
+        }
     }
 }
 
 /// A builder to encrypt and seal a plaintext into a sealed frame
 #[derive(Debug)]
-pub struct FrameBuilderWithDirection<Session, Aes> {
+pub struct FrameBuilderWithDirection<Session, Aes, const RATCHET_THRESHOLD: u32 = DEFAULT_RATCHET_THRESHOLD> {
     /// The underlying session state
     session: Session,
     /// The direction of the associated message
     direction: Direction,
+    /// The piggybacked control channel to attach to the frame, if any
+    fopts: [u8; RawFrame::MAX_FOPTS_SIZE],
+    /// The amount of bytes within `fopts`
+    fopts_len: usize,
     /// The underlying implementation
     _aes: PhantomData<Aes>,
 }
-impl<Session, Aes> FrameBuilderWithDirection<Session, Aes>
+impl<Session, Aes, const RATCHET_THRESHOLD: u32> FrameBuilderWithDirection<Session, Aes, RATCHET_THRESHOLD>
 where
     Session: SessionState,
     Aes: Aes128,
 {
+    /// Attaches a piggybacked control channel (link-check requests, rejoin hints, ADR-style parameters, ...) to the
+    /// frame
+    ///
+    /// # Implementation Note
+    /// `FOpts` is encrypted with its own key stream, distinct from the payload's, and is covered by the MIC; see
+    /// [`RawFrame::set_fopts`].
+    ///
+    /// # Panics
+    /// This function panics if `fopts` is greater than [`RawFrame::MAX_FOPTS_SIZE`].
+    pub fn set_fopts(mut self, fopts: &[u8]) -> Self {
+        self.fopts.get_mut(..fopts.len()).expect("fopts is too large").copy_from_slice(fopts);
+        self.fopts_len = fopts.len();
+        self
+    }
+
     /// Sets the payload and encrypts it abd updates the session accordingly
     ///
     /// # Panics
     /// This function panics if the payload is greater than [`MAX_PAYLOAD_SIZE`](crate::frame::MAX_PAYLOAD_SIZE). This function also panics if if the
     /// frame counter for the configured direction is exhaused.
     pub fn set_payload(mut self, payload: &[u8]) -> Frame {
-        // Get device address and next frame counter
-        let address = self.session.device_address();
-        let next_frame_counter = self.session.frame_counter(self.direction);
-
-        // Assemble frame
-        let mut raw = RawFrame::new(payload);
-        raw.set_address(address);
-        raw.set_frame_counter_lsbs(next_frame_counter as u16);
+        let (mut raw, address, next_frame_counter, generation) = self.assemble(payload);
 
         // Encrypt payload
         let appskey = self.session.appskey();
@@ -63,6 +98,7 @@ where
             .set_direction(self.direction)
             .set_address(address)
             .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
             .apply(raw.payload_mut());
 
         // Compute MIC
@@ -71,7 +107,107 @@ where
             .set_direction(self.direction)
             .set_address(address)
             .set_frame_counter(next_frame_counter)
-            .compute(raw.header(), raw.payload());
+            .set_generation(generation)
+            .compute(raw.header(), raw.fopts(), raw.payload());
+
+        self.finish(raw, next_frame_counter)
+    }
+
+    /// Sets the payload, seals it with a nonce-misuse-resistant, SIV-style payload keystream, and updates the session
+    /// accordingly
+    ///
+    /// # Implementation Note
+    /// Unlike [`Self::set_payload`], the payload keystream here is not derived from the frame counter alone, but from
+    /// the MIC computed over the header, `FOpts` and the *plaintext* payload (see
+    /// [`CipherstreamBuilderWithGeneration::apply_siv`](crate::crypto::stream::CipherstreamBuilderWithGeneration::apply_siv)).
+    /// Two frames that happen to share a frame counter (e.g. after a device reset or a replayed message) therefore
+    /// still get distinct keystreams as long as their plaintexts differ, instead of catastrophically reusing the same
+    /// keystream. The frame is marked via [`RawFrame::set_siv`] so the receiver knows to reverse this order.
+    ///
+    /// # Panics
+    /// This function panics if the payload is greater than [`MAX_PAYLOAD_SIZE`](crate::frame::MAX_PAYLOAD_SIZE). This function also panics if if the
+    /// frame counter for the configured direction is exhaused.
+    pub fn pack_siv(mut self, payload: &[u8]) -> Frame {
+        let (mut raw, address, next_frame_counter, generation) = self.assemble(payload);
+        raw.set_siv(true);
+
+        // Compute the MIC over the header, FOpts and the *plaintext* payload, before encryption
+        let nwkskey = self.session.nwkskey();
+        let mic = MicBuilder::<Aes>::new(nwkskey)
+            .set_direction(self.direction)
+            .set_address(address)
+            .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
+            .compute(raw.header(), raw.fopts(), raw.payload());
+
+        // Seal the payload with the SIV-style keystream derived from that MIC
+        let appskey = self.session.appskey();
+        CipherstreamBuilder::<Aes>::new(appskey)
+            .set_direction(self.direction)
+            .set_address(address)
+            .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
+            .apply_siv(&mic, raw.payload_mut());
+        *raw.mic_mut() = mic;
+
+        self.finish(raw, next_frame_counter)
+    }
+
+    /// Ratchets the session if necessary, assembles the header and encrypts `FOpts`, returning the partially-sealed
+    /// frame along with the context needed to finish sealing the payload
+    fn assemble(&mut self, payload: &[u8]) -> (RawFrame, u32, u32, u8) {
+        // Ratchet the session to the next key generation if the frame counter is approaching exhaustion; sessions
+        // that do not implement `SessionState::ratchet` stay on their current generation forever, in which case the
+        // frame counter eventually panics on exhaustion as before
+        if self.session.frame_counter(self.direction) >= RATCHET_THRESHOLD {
+            let generation = self.session.generation().wrapping_add(1);
+            let nwkskey = ratchet::ratchet::<Aes>(self.session.nwkskey(), NWKSKEY_LABEL, generation);
+            let appskey = ratchet::ratchet::<Aes>(self.session.appskey(), APPSKEY_LABEL, generation);
+            self.session.ratchet(nwkskey, appskey, generation);
+
+            // Only reset the counters if the ratchet actually advanced the generation; otherwise the same keys would
+            // be reused under counters that restart at 0
+            if self.session.generation() == generation {
+                self.session.set_frame_counter(0, Direction::Uplink);
+                self.session.set_frame_counter(0, Direction::Downlink);
+            }
+        }
+
+        // Get device address, next frame counter and generation
+        let address = self.session.device_address();
+        let next_frame_counter = self.session.frame_counter(self.direction);
+        let generation = self.session.generation() as u8;
+
+        // Assemble frame
+        let mut raw = RawFrame::new(payload);
+        raw.set_address(address);
+        raw.set_frame_counter_lsbs(next_frame_counter as u16);
+        raw.set_generation(generation);
+        #[allow(clippy::indexing_slicing)]
+        raw.set_fopts(&self.fopts[..self.fopts_len]);
+
+        // Encrypt FOpts with the network session key, under a key stream distinct from the payload's
+        let nwkskey = self.session.nwkskey();
+        CipherstreamBuilder::<Aes>::new(nwkskey)
+            .set_direction(self.direction)
+            .set_address(address)
+            .set_frame_counter(next_frame_counter)
+            .set_generation(generation)
+            .apply_fopts(raw.fopts_mut());
+
+        (raw, address, next_frame_counter, generation)
+    }
+
+    /// Masks the header (if enabled), commits the next frame counter and returns the final frame
+    fn finish(&mut self, mut raw: RawFrame, next_frame_counter: u32) -> Frame {
+        // Mask the frame-counter LSBs and `FCtrl` so a passive observer cannot read them off the wire
+        #[cfg(feature = "header-protection")]
+        {
+            let nwkskey = self.session.nwkskey();
+            let subkey = header_protection::derive_subkey::<Aes>(nwkskey);
+            let sample = raw.header_protection_sample();
+            raw.apply_header_mask(header_protection::mask::<Aes>(&subkey, &sample));
+        }
 
         // Commit next frame counter
         let next_frame_counter = next_frame_counter.checked_add(1).expect("frame counter is exhaused");
@@ -0,0 +1,131 @@
+//! An ephemeral X25519 handshake to negotiate fresh `nwkskey`/`appskey` pairs, so a [`SessionState`](crate::SessionState)
+//! no longer has to be provisioned with long-lived, out-of-band session keys
+//!
+//! # Implementation Note
+//! Each side generates an [`EphemeralSecret`], exchanges its public key with the other side inside a plaintext init
+//! frame, and authenticates that public key with a CMAC keyed under a pre-shared, long-term root key (so a MITM
+//! cannot substitute its own ephemeral key without holding the root key). Once both public keys are known and
+//! authenticated, both sides compute the same X25519 shared secret and expand it via HKDF-SHA256 into a fresh
+//! `nwkskey`/`appskey` pair, giving forward secrecy across reconnects without requiring an online handshake server.
+
+use crate::crypto::{cipher::generic_array::GenericArray, Aes128};
+use crate::Direction;
+use cmac::{Cmac, Mac};
+use hkdf::Hkdf;
+use rand_core::{CryptoRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// The HKDF info string used to derive the network session key
+const NWKSKEY_INFO: &[u8] = b"loreyawen-nwks";
+/// The HKDF info string used to derive the application session key
+const APPSKEY_INFO: &[u8] = b"loreyawen-apps";
+
+/// One side's ephemeral state in an X25519 handshake authenticated by a pre-shared root key
+pub struct Handshake {
+    /// The ephemeral secret, consumed once the peer's public key is known
+    secret: EphemeralSecret,
+    /// The ephemeral public key to send to the peer
+    public: PublicKey,
+}
+impl core::fmt::Debug for Handshake {
+    /// `x25519_dalek::EphemeralSecret` does not implement `Debug`, so this is hand-written and redacts the secret
+    /// rather than deriving (and thereby failing to compile, or worse, leaking it)
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Handshake").field("secret", &"<redacted>").field("public", &self.public).finish()
+    }
+}
+impl Handshake {
+    /// Generates a fresh ephemeral keypair for a new handshake attempt
+    pub fn new(rng: &mut (impl CryptoRng + RngCore)) -> Self {
+        let secret = EphemeralSecret::random_from_rng(rng);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    /// The ephemeral public key to attach to the plaintext init frame sent to the peer
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Authenticates this side's public key under the pre-shared root key, to attach to the init frame alongside
+    /// [`Self::public_key`]
+    pub fn tag<Aes>(&self, root_key: &[u8; 16], direction: Direction, device_address: u32) -> [u8; 16]
+    where
+        Aes: Aes128,
+    {
+        transcript_tag::<Aes>(root_key, direction, device_address, &self.public.to_bytes())
+    }
+
+    /// Verifies the peer's init frame, completes the X25519 exchange and derives the session keys
+    ///
+    /// # Implementation Note
+    /// `peer_direction` is the direction the peer's init frame was sent in (e.g. an end-device verifying a gateway's
+    /// init frame passes [`Direction::Downlink`]), so the two sides' tags can never be swapped with each other.
+    /// `counter_base` lets both sides agree on a starting frame counter other than `0`, e.g. when resuming a session
+    /// that ratcheted to a later generation.
+    pub fn finish<Aes>(
+        self,
+        root_key: &[u8; 16],
+        device_address: u32,
+        peer_direction: Direction,
+        peer_public_key: [u8; 32],
+        peer_tag: [u8; 16],
+        counter_base: u32,
+    ) -> Option<SessionKeys>
+    where
+        Aes: Aes128,
+    {
+        // Authenticate the peer's public key before trusting it for the DH computation, in constant time
+        let expected = transcript_tag::<Aes>(root_key, peer_direction, device_address, &peer_public_key);
+        let diff = expected.iter().zip(&peer_tag).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+        let true = diff == 0 else {
+            // The peer's public key is not authentic; refuse to derive keys from it
+            return None;
+        };
+
+        // Perform the DH exchange and expand the shared secret into fresh session keys
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer_public_key));
+
+        let mut salt = [0; 8];
+        salt[..4].copy_from_slice(&device_address.to_le_bytes());
+        salt[4..].copy_from_slice(&counter_base.to_le_bytes());
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+
+        let mut nwkskey = [0; 16];
+        hkdf.expand(NWKSKEY_INFO, &mut nwkskey).expect("HKDF output length is within RFC 5869 bounds");
+        let mut appskey = [0; 16];
+        hkdf.expand(APPSKEY_INFO, &mut appskey).expect("HKDF output length is within RFC 5869 bounds");
+
+        Some(SessionKeys { nwkskey, appskey, device_address, frame_counter: counter_base })
+    }
+}
+
+/// Computes the CMAC tag binding a side's ephemeral public key to the pre-shared root key, the handshake direction
+/// and the device address
+fn transcript_tag<Aes>(root_key: &[u8; 16], direction: Direction, device_address: u32, public_key: &[u8; 32]) -> [u8; 16]
+where
+    Aes: Aes128,
+{
+    let key = GenericArray::from_slice(root_key);
+    let mut cmac: Cmac<Aes> = Cmac::new(key);
+    cmac.update(&[direction as u8]);
+    cmac.update(&device_address.to_le_bytes());
+    cmac.update(public_key);
+
+    let mac = cmac.finalize().into_bytes();
+    *mac.first_chunk().expect("MAC is too short")
+}
+
+/// The freshly negotiated session key material, ready to seed a [`SessionState`](crate::SessionState) implementation
+#[derive(Debug, Clone, Copy)]
+pub struct SessionKeys {
+    /// The derived network session key
+    pub nwkskey: [u8; 16],
+    /// The derived application session key
+    pub appskey: [u8; 16],
+    /// The device address the keys are bound to
+    pub device_address: u32,
+    /// The frame counter both directions should start from
+    pub frame_counter: u32,
+}
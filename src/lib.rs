@@ -16,12 +16,14 @@
 #![allow(non_contiguous_range_endpoints, reason = "This lint is stupid")]
 
 pub mod crypto;
+#[cfg(feature = "handshake")]
+pub mod handshake;
 pub mod frame;
 pub mod session;
 
 // Re-export session types
 pub use crate::frame::rawframe::RawFrame;
-pub use crate::session::{Direction, SessionRefMut, SessionState};
+pub use crate::session::{Direction, SessionRefMut, SessionState, SessionStore};
 
 /// A frame builder
 #[cfg(not(feature = "aes"))]
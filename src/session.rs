@@ -23,6 +23,62 @@ pub trait SessionState {
     fn frame_counter(&self, direction: Direction) -> u32;
     /// Sets the frame counter for packets with the given direction
     fn set_frame_counter(&mut self, counter: u32, direction: Direction);
+
+    /// The width of the anti-replay sliding window, in frame counter values
+    ///
+    /// # Implementation Note
+    /// A received frame whose reconstructed counter `c` satisfies `high - REPLAY_WINDOW_WIDTH < c <= high` (where
+    /// `high` is the highest frame counter accepted so far for that direction) may still be accepted out of order, as
+    /// long as `c` has not already been accepted. Implementors that do not want reorder tolerance can leave this at
+    /// its default, in which case [`Self::check_and_set_replay`] is never consulted for such a frame.
+    const REPLAY_WINDOW_WIDTH: u32 = 0;
+
+    /// Checks whether a frame with the given reconstructed counter has already been accepted for the given
+    /// direction, and if not, marks it as seen
+    ///
+    /// # Implementation Note
+    /// This is only called for frames whose MIC has already been validated and whose counter either advances the
+    /// session or falls within the replay window, so a forged frame can never poison the replay state. The default implementation performs no
+    /// tracking and always accepts; implementors that opt into [`Self::REPLAY_WINDOW_WIDTH`] should maintain e.g. a
+    /// bitmap of the last `REPLAY_WINDOW_WIDTH` counters below `high` and reject counters that are already set.
+    fn check_and_set_replay(&mut self, counter: u32, direction: Direction) -> bool {
+        let _ = (counter, direction);
+        true
+    }
+
+    /// The current key generation ("ratchet epoch") for this session
+    ///
+    /// # Implementation Note
+    /// This is encoded in the frame header so that a receiver can detect a generation bump and ratchet its own keys
+    /// to match. Sessions that do not want automatic rekeying (see [`Self::ratchet`]) can leave this at its default;
+    /// the frame counter will then eventually be exhausted as before.
+    fn generation(&self) -> u32 {
+        0
+    }
+
+    /// Replaces the session's working keys with a freshly ratcheted generation and resets both frame counters to
+    /// zero
+    ///
+    /// # Implementation Note
+    /// This is called once a direction's frame counter approaches exhaustion (sealing side), or once a received
+    /// frame's generation byte is ahead of [`Self::generation`] (opening side). The default implementation does
+    /// nothing, opting the session out of automatic rekeying.
+    fn ratchet(&mut self, nwkskey: [u8; 16], appskey: [u8; 16], generation: u32) {
+        let _ = (nwkskey, appskey, generation);
+    }
+}
+
+/// A store of sessions, keyed by device address
+///
+/// # Implementation Note
+/// This lets a gateway or network server that handles many end-devices dispatch an incoming frame to the right
+/// session without already knowing which device sent it; see [`crate::frame::plaintext::dispatch`].
+pub trait SessionStore {
+    /// The concrete session type held by this store
+    type Session: SessionState;
+
+    /// Looks up the session registered for the given device address, if any
+    fn session_mut(&mut self, device_address: u32) -> Option<&mut Self::Session>;
 }
 
 /// Helper type to help implement `SessionState` for any `&mut T where T: SessionState`
@@ -56,4 +112,18 @@ where
     fn set_frame_counter(&mut self, counter: u32, direction: Direction) {
         self.session.set_frame_counter(counter, direction)
     }
+
+    const REPLAY_WINDOW_WIDTH: u32 = T::REPLAY_WINDOW_WIDTH;
+
+    fn check_and_set_replay(&mut self, counter: u32, direction: Direction) -> bool {
+        self.session.check_and_set_replay(counter, direction)
+    }
+
+    fn generation(&self) -> u32 {
+        self.session.generation()
+    }
+
+    fn ratchet(&mut self, nwkskey: [u8; 16], appskey: [u8; 16], generation: u32) {
+        self.session.ratchet(nwkskey, appskey, generation)
+    }
 }
@@ -27,6 +27,7 @@ fn uplink() {
         .set_direction(Direction::Uplink)
         .set_address(SESSION.device_address)
         .set_frame_counter(SESSION.frame_counter_uplink)
+        .set_generation(0)
         .apply(&mut data);
 
     // Validate ciphertext
@@ -42,6 +43,7 @@ fn downlink() {
         .set_direction(Direction::Downlink)
         .set_address(SESSION.device_address)
         .set_frame_counter(SESSION.frame_counter_uplink)
+        .set_generation(0)
         .apply(&mut data);
 
     // Validate ciphertext
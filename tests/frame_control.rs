@@ -0,0 +1,77 @@
+//! Tests sealing and opening control frames
+#![cfg(feature = "aes")]
+
+mod session;
+
+use loreyawen::crypto::aes::Aes128;
+use loreyawen::frame::control::{ControlFrameBuilder, ControlFrameOpener};
+use loreyawen::Direction;
+use session::MockSession;
+use std::ops::Deref;
+
+/// The mock session to use in the tests
+pub const SESSION: MockSession = MockSession {
+    nwkskey: *b"\x00\x11\x22\x33\x44\x55\x66\x77\x88\x99\xAA\xBB\xCC\xDD\xEE\xFF",
+    appskey: *b"\xFF\xEE\xDD\xCC\xBB\xAA\x99\x88\x77\x66\x55\x44\x33\x22\x11\x00",
+    device_address: 0xDEADBEEF,
+    frame_counter_uplink: 0,
+    frame_counter_downlink: 0,
+};
+
+/// A control frame sealed for one direction must round-trip back to the same commands on the other end
+#[test]
+pub fn uplink_downlink_round_trip() {
+    // Seal an uplink control frame
+    let mut sealer = SESSION;
+    let frame =
+        ControlFrameBuilder::<_, Aes128>::new(&mut sealer).set_direction(Direction::Uplink).set_payload(b"Testolope");
+    assert_eq!(sealer.frame_counter_uplink, 1, "invalid uplink frame counter");
+
+    // Open it back up
+    let mut opener = SESSION;
+    let commands = ControlFrameOpener::<_, Aes128>::new(&mut opener)
+        .set_direction(Direction::Uplink)
+        .set_frame(&frame)
+        .expect("unexpected invalid frame")
+        .unpack()
+        .expect("unexpected failure when unpacking frame");
+    assert_eq!(commands.deref(), b"Testolope");
+    assert_eq!(opener.frame_counter_uplink, 1, "invalid uplink frame counter");
+
+    // Seal a downlink control frame
+    let mut sealer = SESSION;
+    let frame = ControlFrameBuilder::<_, Aes128>::new(&mut sealer)
+        .set_direction(Direction::Downlink)
+        .set_payload(b"Testolope");
+    assert_eq!(sealer.frame_counter_downlink, 1, "invalid downlink frame counter");
+
+    // Open it back up
+    let mut opener = SESSION;
+    let commands = ControlFrameOpener::<_, Aes128>::new(&mut opener)
+        .set_direction(Direction::Downlink)
+        .set_frame(&frame)
+        .expect("unexpected invalid frame")
+        .unpack()
+        .expect("unexpected failure when unpacking frame");
+    assert_eq!(commands.deref(), b"Testolope");
+    assert_eq!(opener.frame_counter_downlink, 1, "invalid downlink frame counter");
+}
+
+/// A control frame whose MIC has been tampered with must be rejected
+#[test]
+pub fn tampered_mic_is_rejected() {
+    // Seal a control frame, then flip a bit in the on-wire MIC
+    let mut sealer = SESSION;
+    let mut frame: Vec<u8> =
+        ControlFrameBuilder::<_, Aes128>::new(&mut sealer).set_direction(Direction::Uplink).set_payload(b"Testolope").to_vec();
+    *frame.last_mut().expect("frame is empty") ^= 0x01;
+
+    // Opening the tampered frame must fail
+    let mut opener = SESSION;
+    let maybe_commands = ControlFrameOpener::<_, Aes128>::new(&mut opener)
+        .set_direction(Direction::Uplink)
+        .set_frame(&frame)
+        .expect("unexpected invalid frame")
+        .unpack();
+    assert!(maybe_commands.is_none(), "unexpected success when unpacking a tampered frame");
+}
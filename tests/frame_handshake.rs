@@ -0,0 +1,64 @@
+//! Tests the X25519 handshake used to negotiate fresh session keys
+#![cfg(feature = "handshake")]
+#![cfg(feature = "aes")]
+
+use loreyawen::crypto::aes::Aes128;
+use loreyawen::handshake::Handshake;
+use loreyawen::Direction;
+use rand_core::OsRng;
+
+/// The pre-shared root key both sides authenticate their ephemeral public key under
+const ROOT_KEY: [u8; 16] = *b"\x00\x11\x22\x33\x44\x55\x66\x77\x88\x99\xAA\xBB\xCC\xDD\xEE\xFF";
+/// The device address both sides agree the handshake is for
+const DEVICE_ADDRESS: u32 = 0xDEAD_BEEF;
+
+/// A legitimate handshake between a device and a gateway must derive identical session keys on both sides
+#[test]
+fn round_trip() {
+    let device = Handshake::new(&mut OsRng);
+    let gateway = Handshake::new(&mut OsRng);
+    let (device_public, gateway_public) = (device.public_key(), gateway.public_key());
+
+    let device_tag = device.tag::<Aes128>(&ROOT_KEY, Direction::Uplink, DEVICE_ADDRESS);
+    let gateway_tag = gateway.tag::<Aes128>(&ROOT_KEY, Direction::Downlink, DEVICE_ADDRESS);
+
+    let device_keys = device
+        .finish::<Aes128>(&ROOT_KEY, DEVICE_ADDRESS, Direction::Downlink, gateway_public, gateway_tag, 0)
+        .expect("a legitimate handshake must be accepted");
+    let gateway_keys = gateway
+        .finish::<Aes128>(&ROOT_KEY, DEVICE_ADDRESS, Direction::Uplink, device_public, device_tag, 0)
+        .expect("a legitimate handshake must be accepted");
+
+    assert_eq!(device_keys.nwkskey, gateway_keys.nwkskey, "both sides must derive the same network session key");
+    assert_eq!(device_keys.appskey, gateway_keys.appskey, "both sides must derive the same application session key");
+    assert_eq!(device_keys.device_address, DEVICE_ADDRESS);
+}
+
+/// A tampered peer tag must be rejected rather than silently accepted
+#[test]
+fn tampered_peer_tag_is_rejected() {
+    let device = Handshake::new(&mut OsRng);
+    let gateway = Handshake::new(&mut OsRng);
+    let gateway_public = gateway.public_key();
+
+    let mut gateway_tag = gateway.tag::<Aes128>(&ROOT_KEY, Direction::Downlink, DEVICE_ADDRESS);
+    gateway_tag[0] ^= 0x01;
+
+    let keys = device.finish::<Aes128>(&ROOT_KEY, DEVICE_ADDRESS, Direction::Downlink, gateway_public, gateway_tag, 0);
+    assert!(keys.is_none(), "a tampered peer tag must not derive session keys");
+}
+
+/// A handshake finished under the wrong root key must be rejected rather than derive diverging session keys
+#[test]
+fn wrong_root_key_is_rejected() {
+    const OTHER_ROOT_KEY: [u8; 16] = *b"\xFF\xEE\xDD\xCC\xBB\xAA\x99\x88\x77\x66\x55\x44\x33\x22\x11\x00";
+
+    let device = Handshake::new(&mut OsRng);
+    let gateway = Handshake::new(&mut OsRng);
+    let gateway_public = gateway.public_key();
+    let gateway_tag = gateway.tag::<Aes128>(&ROOT_KEY, Direction::Downlink, DEVICE_ADDRESS);
+
+    let keys =
+        device.finish::<Aes128>(&OTHER_ROOT_KEY, DEVICE_ADDRESS, Direction::Downlink, gateway_public, gateway_tag, 0);
+    assert!(keys.is_none(), "a tag authenticated under a different root key must not be accepted");
+}
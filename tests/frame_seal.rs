@@ -90,6 +90,32 @@ pub fn downlink() {
     assert_eq!(session.frame_counter_downlink, 2, "invalid downlink frame counter");
 }
 
+/// A session that never overrides [`loreyawen::SessionState::ratchet`] never actually advances its key generation,
+/// so hitting the ratchet threshold must not reset the frame counter back to `0` - that would reuse the very same
+/// `nwkskey`/`appskey` under a counter that was already used just before the "ratchet"
+#[test]
+pub fn ratchet_without_override_keeps_counting() {
+    use loreyawen::frame::builder::DEFAULT_RATCHET_THRESHOLD;
+
+    let mut session = SESSION;
+    session.frame_counter_uplink = DEFAULT_RATCHET_THRESHOLD;
+
+    let first = FrameBuilder::new(&mut session).set_direction(Direction::Uplink).set_plaintext(b"Testolope").pack();
+    assert_eq!(
+        session.frame_counter_uplink,
+        DEFAULT_RATCHET_THRESHOLD + 1,
+        "counter was reset despite no generation bump"
+    );
+
+    let second = FrameBuilder::new(&mut session).set_direction(Direction::Uplink).set_plaintext(b"Testolope").pack();
+    assert_eq!(session.frame_counter_uplink, DEFAULT_RATCHET_THRESHOLD + 2);
+
+    // Both frames stay on generation `0` (no override), yet must carry distinct frame counters
+    assert_eq!(first[9], 0, "unexpected generation byte");
+    assert_eq!(second[9], 0, "unexpected generation byte");
+    assert_ne!(&first[6..8], &second[6..8], "counter/key pair was reused across the ratchet threshold");
+}
+
 #[test]
 #[should_panic]
 pub fn exhausted_frame_counter() {
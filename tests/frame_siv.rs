@@ -0,0 +1,66 @@
+//! Tests the SIV-style nonce-misuse-resistant sealing mode, specifically deliberate frame-counter collisions
+#![cfg(feature = "aes")]
+
+mod session;
+
+use loreyawen::{
+    crypto::aes::Aes128,
+    frame::{plaintext::PlaintextBuilder, sealed::FrameBuilder},
+    Direction,
+};
+use session::MockSession;
+use std::ops::Deref;
+
+/// The mock session to use in the tests
+pub const SESSION: MockSession = MockSession {
+    nwkskey: *b"\x00\x11\x22\x33\x44\x55\x66\x77\x88\x99\xAA\xBB\xCC\xDD\xEE\xFF",
+    appskey: *b"\xFF\xEE\xDD\xCC\xBB\xAA\x99\x88\x77\x66\x55\x44\x33\x22\x11\x00",
+    device_address: 0xDEADBEEF,
+    frame_counter_uplink: 0,
+    frame_counter_downlink: 0,
+};
+
+/// Two frames deliberately sealed under the same frame counter (e.g. after a device reset that lost its counter
+/// state) must still get distinct keystreams as long as their plaintexts differ, and both must still round-trip
+/// correctly on the receiving end
+#[test]
+pub fn colliding_frame_counter_does_not_reuse_keystream() {
+    // Seal two different, same-length payloads under the very same frame counter
+    let mut sealer = SESSION;
+    let first = FrameBuilder::<_, Aes128>::new(&mut sealer).set_direction(Direction::Uplink).pack_siv(b"Testolope");
+    assert_eq!(sealer.frame_counter_uplink, 1, "invalid uplink frame counter");
+
+    // Force the colliding counter, as if the device had reset and lost its counter state
+    sealer.frame_counter_uplink = 0;
+    let second = FrameBuilder::<_, Aes128>::new(&mut sealer).set_direction(Direction::Uplink).pack_siv(b"TESTOLOPE");
+    assert_eq!(sealer.frame_counter_uplink, 1, "invalid uplink frame counter");
+
+    // Both frames transmit the very same (colliding) frame-counter LSBs...
+    assert_eq!(&first[6..8], &second[6..8], "test setup is broken: frame counters do not actually collide");
+
+    // ... yet a keystream reused across both payloads would make the ciphertexts XOR to the same value as the
+    // plaintexts; SIV derives the keystream from the (distinct) MICs instead, so that must not hold here. The payload
+    // starts right after the 10-byte header (there is no `FOpts` here).
+    let plaintext_xor: Vec<u8> = b"Testolope".iter().zip(b"TESTOLOPE").map(|(a, b)| a ^ b).collect();
+    let ciphertext_xor: Vec<u8> = first[10..19].iter().zip(&second[10..19]).map(|(a, b)| a ^ b).collect();
+    assert_ne!(ciphertext_xor[..], plaintext_xor[..], "ciphertexts leak the plaintext XOR despite the SIV mode");
+
+    // Both frames must still round-trip correctly, each against a receiver expecting the same colliding counter
+    let mut first_opener = SESSION;
+    let plaintext = PlaintextBuilder::<_, Aes128>::new(&mut first_opener)
+        .set_direction(Direction::Uplink)
+        .set_frame(&first)
+        .expect("unexpected invalid frame")
+        .unpack()
+        .expect("unexpected failure when unpacking the first frame");
+    assert_eq!(plaintext.deref(), b"Testolope");
+
+    let mut second_opener = SESSION;
+    let plaintext = PlaintextBuilder::<_, Aes128>::new(&mut second_opener)
+        .set_direction(Direction::Uplink)
+        .set_frame(&second)
+        .expect("unexpected invalid frame")
+        .unpack()
+        .expect("unexpected failure when unpacking the second frame");
+    assert_eq!(plaintext.deref(), b"TESTOLOPE");
+}